@@ -1,7 +1,8 @@
 #![allow(dead_code)]
 
-use bitcask::Bitcask;
+use bitcask::{Bitcask, KeyValueStorage, Options, SyncPolicy};
 use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use rand::{
     distributions::{Distribution, Standard, Uniform},
     Rng,
@@ -44,4 +45,53 @@ fn get_bitcask() -> (Bitcask, TempDir) {
     let tmpdir = TempDir::new().unwrap();
     let bitcask = Bitcask::open(tmpdir.path()).unwrap();
     (bitcask, tmpdir)
-}
\ No newline at end of file
+}
+
+fn get_bitcask_with_sync_policy(sync_policy: SyncPolicy) -> (Bitcask, TempDir) {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask =
+        Bitcask::open_with(tmpdir.path(), Options::default().sync_policy(sync_policy)).unwrap();
+    (bitcask, tmpdir)
+}
+
+const WRITES: usize = 256;
+
+fn concurrent_put_sync_always(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("concurrent_put_sync_always");
+    group.sample_size(10);
+
+    for tasks in [1, 8, 64] {
+        let (bitcask, _tmpdir) = get_bitcask_with_sync_policy(SyncPolicy::Always);
+        let handle = bitcask.get_handle();
+        let pairs = KeyValuePair::random_many(&mut rand::thread_rng(), WRITES, 64, 256);
+
+        group.bench_with_input(BenchmarkId::from_parameter(tasks), &tasks, |b, &tasks| {
+            b.to_async(&runtime).iter(|| {
+                let handle = handle.clone();
+                let pairs = pairs.clone();
+                async move {
+                    let chunks = pairs.chunks(WRITES / tasks).map(<[KeyValuePair]>::to_vec);
+                    let writers: Vec<_> = chunks
+                        .map(|chunk| {
+                            let handle = handle.clone();
+                            tokio::task::spawn_blocking(move || {
+                                for KeyValuePair(key, value) in chunk {
+                                    handle.set(key, value).unwrap();
+                                }
+                            })
+                        })
+                        .collect();
+                    for writer in writers {
+                        writer.await.unwrap();
+                    }
+                }
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_put_sync_always);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+//! Takes full and incremental checkpoints of a store and restores them.
+//!
+//! ```text
+//! backup full <store> <dest>
+//! backup incremental <store> <dest> <base>
+//! backup restore <dest> <full> [<incremental>...]
+//! ```
+//!
+//! Opening the store takes its lock, so `full` and `incremental` only work
+//! while no other process writes to it. Services should call
+//! `Handle::checkpoint` and `Handle::checkpoint_incremental` instead.
+
+use std::env;
+
+use anyhow::{bail, Result};
+use bitcask::Bitcask;
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["full", store, dest] => Bitcask::open(store)?.get_handle().checkpoint(dest)?,
+        ["incremental", store, dest, base] => Bitcask::open(store)?
+            .get_handle()
+            .checkpoint_incremental(dest, base)?,
+        ["restore", dest, ref backups @ ..] if !backups.is_empty() => {
+            Bitcask::restore(backups, dest)?
+        }
+        _ => bail!(
+            "usage: backup full <store> <dest>\n       \
+             backup incremental <store> <dest> <base>\n       \
+             backup restore <dest> <full> [<incremental>...]"
+        ),
+    }
+    Ok(())
+}
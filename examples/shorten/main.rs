@@ -5,7 +5,7 @@ use axum::{
     routing::get,
     Form, Router,
 };
-use bitcask::{Handle, KeyValueStorage};
+use bitcask::{AsyncHandle, AsyncKeyValueStorage};
 use bytes::Bytes;
 use rand::{distributions::Alphanumeric, Rng};
 use serde::Deserialize;
@@ -19,7 +19,8 @@ async fn main() -> Result<()> {
     let app = Router::new()
         .route("/", get(index).post(post_url))
         .route("/s/:id", get(get_url))
-        .with_state(db.get_handle());
+        .route("/metrics", get(metrics))
+        .with_state(db.get_async_handle());
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
 
@@ -46,20 +47,32 @@ struct Input {
     url: Bytes,
 }
 
-async fn post_url(State(db): State<Handle>, Form(input): Form<Input>) -> String{
-    let rng = rand::thread_rng();
-    let id = rng.sample_iter(&Alphanumeric).take(8).collect::<Bytes>();
-    match db.set(id.clone(), input.url) {
-        Ok(_) => unsafe { "http://192.168.122.1:3000/s/".to_string() + std::str::from_utf8_unchecked(&id) },
+async fn post_url(State(db): State<AsyncHandle>, Form(input): Form<Input>) -> String{
+    // Draw ids until one is free, so a collision never overwrites a link.
+    let id = loop {
+        let rng = rand::thread_rng();
+        let id = rng.sample_iter(&Alphanumeric).take(8).collect::<Bytes>();
+        match db.put_if_absent(id.clone(), input.url.clone()).await {
+            Ok(false) => continue,
+            Ok(true) => break Ok(id),
+            Err(e) => break Err(e),
+        }
+    };
+    match id {
+        Ok(id) => unsafe { "http://192.168.122.1:3000/s/".to_string() + std::str::from_utf8_unchecked(&id) },
         Err(_) => String::from("Error"),
     }
 }
 
-async fn get_url(Path(id): Path<Bytes>, State(db): State<Handle>) -> impl IntoResponse{
-    match db.get(id).unwrap() {
+async fn get_url(Path(id): Path<Bytes>, State(db): State<AsyncHandle>) -> impl IntoResponse{
+    match db.get(id).await.unwrap() {
         Some(url) => Redirect::permanent(unsafe {
             std::str::from_utf8_unchecked(&url)
         }),
         None => Redirect::permanent("/"),
     }
-}
\ No newline at end of file
+}
+
+async fn metrics(State(db): State<AsyncHandle>) -> String {
+    db.get_handle().metrics().render()
+}
@@ -0,0 +1,117 @@
+use std::{
+    panic,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+
+use crate::{metrics::Op, AsyncKeyValueStorage, Error, Handle, Subscription, WriteBatch};
+
+/// A [`Handle`] for async code. Waiting for a reader does not block the
+/// executor, and writes run on tokio's blocking pool, where they wait for the
+/// writer lock and for any `fsync` the sync policy asks for.
+///
+/// Must be used from within a tokio runtime.
+#[derive(Clone, Debug)]
+pub struct AsyncHandle {
+    handle: Handle,
+}
+
+impl AsyncHandle {
+    pub(super) fn new(handle: Handle) -> Self {
+        Self { handle }
+    }
+
+    async fn get(&self, key: Bytes) -> Result<Option<Bytes>, Error> {
+        let metrics = &self.handle.ctx.metrics;
+        let start = Instant::now();
+        if self.handle.ctx.is_closed() {
+            return metrics.time(Op::Get, || Err(Error::Closed));
+        }
+        let permit = self
+            .handle
+            .reader_permits
+            .acquire()
+            .await
+            .expect("reader permits are never closed");
+        metrics.reader_wait(start.elapsed());
+        metrics.time_since(Op::Get, start, || self.handle.read(permit, key))
+    }
+
+    /// See [`Handle::set_with_ttl`].
+    pub async fn set_with_ttl(&self, key: Bytes, value: Bytes, ttl: Duration) -> Result<(), Error> {
+        self.blocking(move |handle| handle.set_with_ttl(key, value, ttl))
+            .await
+    }
+
+    /// See [`Handle::compare_and_swap`].
+    pub async fn compare_and_swap(
+        &self,
+        key: Bytes,
+        expected: Option<Bytes>,
+        new: Option<Bytes>,
+    ) -> Result<bool, Error> {
+        self.blocking(move |handle| handle.compare_and_swap(key, expected, new))
+            .await
+    }
+
+    /// See [`Handle::put_if_absent`].
+    pub async fn put_if_absent(&self, key: Bytes, value: Bytes) -> Result<bool, Error> {
+        self.blocking(move |handle| handle.put_if_absent(key, value))
+            .await
+    }
+
+    /// See [`Handle::delete_if_equals`].
+    pub async fn delete_if_equals(&self, key: Bytes, expected: Bytes) -> Result<bool, Error> {
+        self.blocking(move |handle| handle.delete_if_equals(key, expected))
+            .await
+    }
+
+    /// See [`Handle::write_batch`].
+    pub async fn write_batch(&self, batch: WriteBatch) -> Result<(), Error> {
+        self.blocking(move |handle| handle.write_batch(batch)).await
+    }
+
+    /// See [`Handle::sync`].
+    pub async fn sync(&self) -> Result<(), Error> {
+        self.blocking(Handle::sync).await
+    }
+
+    /// See [`Handle::subscribe`].
+    pub fn subscribe(&self, prefix: Bytes) -> Result<Subscription, Error> {
+        self.handle.subscribe(prefix)
+    }
+
+    /// The blocking handle this one wraps.
+    pub fn get_handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
+    async fn blocking<T, F>(&self, f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&Handle) -> T + Send + 'static,
+    {
+        let handle = self.handle.clone();
+        match tokio::task::spawn_blocking(move || f(&handle)).await {
+            Ok(result) => result,
+            Err(e) => panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+impl AsyncKeyValueStorage for AsyncHandle {
+    type Error = Error;
+
+    async fn set(&self, key: Bytes, value: Bytes) -> Result<(), Self::Error> {
+        self.blocking(move |handle| handle.put(key, value)).await
+    }
+
+    async fn get(&self, key: Bytes) -> Result<Option<Bytes>, Self::Error> {
+        self.get(key).await
+    }
+
+    async fn del(&self, key: Bytes) -> Result<bool, Self::Error> {
+        self.blocking(move |handle| handle.del(key)).await
+    }
+}
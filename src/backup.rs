@@ -0,0 +1,136 @@
+use std::{fs, io, path::Path};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    context::Context,
+    log::{self, LogIterator},
+    merge::Merger,
+    utils,
+    writer::Writer,
+    Error,
+};
+
+/// Lists the data files of a checkpoint. It is the last file written, so a
+/// checkpoint without one is incomplete.
+///
+/// An incremental checkpoint only holds the files that are newer than every
+/// file of the checkpoint it builds on, but its manifest still lists all the
+/// files the store had. Sealed files never change and new ones always get
+/// higher ids, so the others can be found in earlier checkpoints.
+#[derive(Serialize, Deserialize, Debug)]
+struct Manifest {
+    fileids: Vec<u64>,
+}
+
+/// Copies the sealed data files and their hint files into `dest`, which must
+/// not hold a store already, after sealing the active data file. With a
+/// `base` checkpoint, only files newer than those in it are copied. The
+/// keyspace registry is always copied in full.
+pub(super) fn checkpoint<P: AsRef<Path>>(
+    ctx: &Context,
+    writer: &Mutex<Writer>,
+    merger: &Mutex<Merger>,
+    dest: P,
+    base: Option<&Path>,
+) -> Result<(), Error> {
+    let newer_than = match base {
+        Some(base) => read_manifest(base)?.fileids.last().copied(),
+        None => None,
+    };
+    fs::create_dir_all(&dest)?;
+    if utils::manifest_name(&dest).exists() || utils::sorted_fileids(&dest)?.next().is_some() {
+        return Err(Error::AlreadyExists);
+    }
+
+    // Holding the merger keeps the sealed files from being merged away while
+    // they are copied.
+    let _merger = merger.lock();
+    let active_fileid = writer.lock().rotate()?;
+    let fileids: Vec<u64> = utils::sorted_fileids(&ctx.path)?
+        .filter(|&id| id < active_fileid)
+        .collect();
+    for &fileid in &fileids {
+        if newer_than.is_none_or(|id| fileid > id) {
+            copy_fileid(&ctx.path, &dest, fileid)?;
+        }
+    }
+    copy_keyspaces(&ctx.path, &dest)?;
+
+    log::write_atomic(utils::manifest_name(&dest), &[Manifest { fileids }])
+}
+
+/// Reassembles a store in `dest` from a full checkpoint followed by the
+/// incremental checkpoints built on it, oldest first. The store is restored
+/// as of the last checkpoint.
+pub(super) fn restore<P: AsRef<Path>, Q: AsRef<Path>>(backups: &[P], dest: Q) -> Result<(), Error> {
+    let Some(last) = backups.last() else {
+        return Ok(());
+    };
+    let manifest = read_manifest(last)?;
+    fs::create_dir_all(&dest)?;
+    if utils::manifest_name(&dest).exists() || utils::sorted_fileids(&dest)?.next().is_some() {
+        return Err(Error::AlreadyExists);
+    }
+
+    for &fileid in &manifest.fileids {
+        let backup = backups
+            .iter()
+            .rev()
+            .find(|backup| utils::datafile_name(backup, fileid).exists())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("data file {fileid} is in none of the checkpoints"),
+                )
+            })?;
+        copy_fileid(backup, &dest, fileid)?;
+    }
+    copy_keyspaces(last, &dest)?;
+
+    log::write_atomic(utils::manifest_name(&dest), &[manifest])
+}
+
+/// Brings the data file `fileid` and its hint file, if any, from `src` to
+/// `dest`.
+fn copy_fileid<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dest: Q, fileid: u64) -> io::Result<()> {
+    link_or_copy(
+        utils::datafile_name(&src, fileid),
+        utils::datafile_name(&dest, fileid),
+    )?;
+    let hintfile = utils::hintfile_name(&src, fileid);
+    if hintfile.exists() {
+        link_or_copy(hintfile, utils::hintfile_name(&dest, fileid))?;
+    }
+    Ok(())
+}
+
+/// Brings the keyspace registry, if any, from `src` to `dest`. It is replaced
+/// rather than changed in place, so a hard link keeps the current version.
+fn copy_keyspaces<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dest: Q) -> io::Result<()> {
+    let registry = utils::keyspaces_name(&src);
+    if registry.exists() {
+        link_or_copy(registry, utils::keyspaces_name(&dest))?;
+    }
+    Ok(())
+}
+
+/// Hard-links `src` to `dst`, falling back to a synced copy where that is not
+/// possible, e.g. across file systems. Either is fine for sealed files, which
+/// never change.
+fn link_or_copy<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> io::Result<()> {
+    if fs::hard_link(&src, &dst).is_ok() {
+        return Ok(());
+    }
+    fs::copy(&src, &dst)?;
+    fs::File::open(&dst)?.sync_all()
+}
+
+fn read_manifest<P: AsRef<Path>>(path: P) -> Result<Manifest, Error> {
+    let file = log::open(utils::manifest_name(path))?;
+    match LogIterator::new(file, 0)?.next::<Manifest>()? {
+        Some((_, manifest)) => Ok(manifest),
+        None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+    }
+}
@@ -0,0 +1,32 @@
+use bytes::Bytes;
+
+/// A group of puts and deletes applied by
+/// [`Handle::write_batch`](crate::Handle::write_batch) as one unit: after a
+/// crash either all of them are recovered or none, and readers never see
+/// some of them without the others.
+#[derive(Clone, Debug, Default)]
+pub struct WriteBatch {
+    pub(super) ops: Vec<(Bytes, Option<Bytes>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: Bytes, value: Bytes) {
+        self.ops.push((key, Some(value)));
+    }
+
+    pub fn delete(&mut self, key: Bytes) {
+        self.ops.push((key, None));
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
@@ -1,4 +1,7 @@
-use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+};
 
 #[derive(Debug)]
 pub(super) struct BufReaderWithPos<R: Read> {
@@ -22,18 +25,16 @@ impl<R: Read> BufReaderWithPos<R> {
 
 impl<R: Read> Read for BufReaderWithPos<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.reader.read(buf).map(|bytes_read| {
+        self.reader.read(buf).inspect(|&bytes_read| {
             self.pos += bytes_read as u64;
-            bytes_read
         })
     }
 }
 
 impl<R: Read + Seek> Seek for BufReaderWithPos<R> {
     fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
-        self.reader.seek(pos).map(|pos_num| {
+        self.reader.seek(pos).inspect(|&pos_num| {
             self.pos = pos_num;
-            pos_num
         })
     }
 }
@@ -51,6 +52,20 @@ impl<W: Write + Seek> BufWriterWithPos<W> {
     }
 }
 
+impl BufWriterWithPos<fs::File> {
+    /// Cuts the file back to `len` bytes, throwing away whatever is still
+    /// buffered past it.
+    pub(super) fn truncate(&mut self, len: u64) -> io::Result<()> {
+        let file = self.writer.get_ref().try_clone()?;
+        // Taken apart rather than dropped, which would flush the buffer.
+        let _ = std::mem::replace(&mut self.writer, BufWriter::new(file)).into_parts();
+        let file = self.writer.get_mut();
+        file.set_len(len)?;
+        self.pos = file.seek(SeekFrom::Start(len))?;
+        Ok(())
+    }
+}
+
 impl<W: Write> BufWriterWithPos<W> {
     pub(super) fn pos(&self) -> u64 {
         self.pos
@@ -63,9 +78,8 @@ impl<W: Write> BufWriterWithPos<W> {
 
 impl<W: Write> Write for BufWriterWithPos<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.writer.write(buf).map(|bytes_written| {
+        self.writer.write(buf).inspect(|&bytes_written| {
             self.pos += bytes_written as u64;
-            bytes_written
         })
     }
 
@@ -76,9 +90,8 @@ impl<W: Write> Write for BufWriterWithPos<W> {
 
 impl<W: Write + Seek> Seek for BufWriterWithPos<W> {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
-        self.writer.seek(pos).map(|pos_num| {
+        self.writer.seek(pos).inspect(|&pos_num| {
             self.pos = pos_num;
-            pos_num
         })
     }
 }
@@ -0,0 +1,323 @@
+use std::{collections::VecDeque, io, sync::Arc, thread, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{
+    self,
+    error::{RecvError, TryRecvError},
+};
+
+use crate::{
+    context::Context,
+    log::{self, LogIterator},
+    merge, utils, BatchMarker, DataFileEntry, Error, Event,
+};
+
+/// A position in the data files, right after a write. Handed out with every
+/// event by [`Changes`] and meant to be stored, so that a reader can resume
+/// from it after a restart.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct ChangeCursor {
+    pub fileid: u64,
+    pub pos: u64,
+}
+
+/// Iterator over the writes made after a [`ChangeCursor`], read from the data
+/// files and returned by [`Handle::changes`](crate::Handle::changes).
+///
+/// Each event comes with the cursor right after it. Records are read in the
+/// order they were appended, following the writer from one data file to the
+/// next, and a write batch is only yielded once it is committed. Files
+/// written by merges are skipped, since they only hold copies of older
+/// writes.
+///
+/// Once it has caught up, the iterator blocks until more is written: a
+/// handle that owns the writer is woken up by every write, a read-only one
+/// checks the data files every [`poll_interval`](Self::poll_interval). It
+/// stops after yielding an error, such as [`Error::CursorTooOld`] when a merge
+/// removed writes that were not read yet, or [`Error::Closed`] when the store
+/// is closed. Must not be iterated from async code.
+#[derive(Debug)]
+pub struct Changes {
+    ctx: Arc<Context>,
+    /// Id of the keyspace whose writes are yielded.
+    keyspace: u32,
+    /// Where to continue reading the data files.
+    next: ChangeCursor,
+    /// Right after the last event returned.
+    cursor: ChangeCursor,
+    pending: VecDeque<(Event, ChangeCursor)>,
+    wakeups: Option<broadcast::Receiver<(u32, Event)>>,
+    poll_interval: Duration,
+    done: bool,
+}
+
+impl Changes {
+    pub(super) fn new(
+        ctx: Arc<Context>,
+        keyspace: u32,
+        cursor: ChangeCursor,
+        wakeups: Option<broadcast::Receiver<(u32, Event)>>,
+    ) -> Self {
+        Self {
+            ctx,
+            keyspace,
+            next: cursor,
+            cursor,
+            pending: VecDeque::new(),
+            wakeups,
+            poll_interval: Duration::from_millis(100),
+            done: false,
+        }
+    }
+
+    /// How often a read-only handle checks for new writes once it caught up.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Position right after the last event returned. Unlike the cursor that
+    /// came with that event, it moves on to the next data file once the
+    /// current one was read to its end, so it stays valid after a merge
+    /// removes that file.
+    pub fn cursor(&self) -> ChangeCursor {
+        self.cursor
+    }
+
+    /// Returns the next event if one was written, without waiting.
+    pub fn try_next(&mut self) -> Result<Option<(Event, ChangeCursor)>, Error> {
+        if self.pending.is_empty() {
+            self.fill()?;
+        }
+        match self.pending.pop_front() {
+            Some((event, cursor)) => {
+                self.cursor = cursor;
+                Ok(Some((event, cursor)))
+            }
+            None => {
+                self.cursor = self.next;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Waits until more may have been written.
+    fn wait(&mut self) -> Result<(), Error> {
+        match &mut self.wakeups {
+            Some(wakeups) => {
+                match wakeups.blocking_recv() {
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return Err(Error::Closed),
+                }
+                // Writes woken up for since are read all at once.
+                while let Ok(_) | Err(TryRecvError::Lagged(_)) = wakeups.try_recv() {}
+                Ok(())
+            }
+            None if self.ctx.is_closed() => Err(Error::Closed),
+            None => {
+                thread::sleep(self.poll_interval);
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads whatever was appended since the last call, moving on to newer
+    /// data files as long as nothing is found.
+    fn fill(&mut self) -> Result<(), Error> {
+        loop {
+            // The file is read to its end only after checking for a newer
+            // one, which the writer starts once this one is sealed.
+            let sealed = utils::sorted_fileids(&self.ctx.path)?.any(|id| id > self.next.fileid);
+            match self.read(sealed) {
+                Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+                    if !self.skip_missing()? {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                result => result?,
+            }
+            if !self.pending.is_empty() || !sealed || !self.advance(self.next.fileid + 1)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Queues the events from `self.next` to the end of its data file.
+    fn read(&mut self, sealed: bool) -> Result<(), Error> {
+        let ChangeCursor { fileid, pos } = self.next;
+        let file = log::open(utils::datafile_name(&self.ctx.path, fileid))?;
+        let mut datafile_iter = LogIterator::new_at(file, fileid, pos)?;
+        // The number of records still to come of the open batch, and the
+        // events of those read.
+        let mut batch: Option<(u64, Vec<_>)> = None;
+        loop {
+            let (index, entry) = match datafile_iter.next::<DataFileEntry>() {
+                Ok(Some(entry)) => entry,
+                Ok(None) => break,
+                // The writer is in the middle of appending it.
+                Err(Error::Corruption { .. } | Error::Serialization(_)) if !sealed => break,
+                Err(e) => return Err(e),
+            };
+            let end = ChangeCursor {
+                fileid,
+                pos: index.pos + index.len,
+            };
+            let keyspace = entry.keyspace;
+            // A batch left open by a failed write is dropped once a record
+            // past its end or another batch shows up.
+            if entry.batch.is_none() && batch.as_ref().is_some_and(|(left, _)| *left == 0) {
+                batch = None;
+            }
+            match (entry.batch, &mut batch) {
+                (Some(BatchMarker::Begin), _) => {
+                    let len = entry.batch_len().ok_or(Error::Corruption {
+                        fileid,
+                        pos: index.pos,
+                    })?;
+                    batch = Some((len, Vec::new()));
+                }
+                (Some(BatchMarker::Commit), Some(_)) => {
+                    let mut events: Vec<_> = batch
+                        .take()
+                        .into_iter()
+                        .flat_map(|(_, events)| events)
+                        .collect();
+                    // Resuming past the last write of a batch skips its marker.
+                    if let Some((_, cursor)) = events.last_mut() {
+                        *cursor = end;
+                    }
+                    self.pending.extend(events);
+                    self.next = end;
+                }
+                (Some(BatchMarker::Commit | BatchMarker::Merge), None) => self.next = end,
+                (Some(BatchMarker::Merge), Some(_)) => {}
+                // Writes to other keyspaces only move the cursor on.
+                (None, Some((left, _))) if keyspace != self.keyspace => *left -= 1,
+                (None, None) if keyspace != self.keyspace => self.next = end,
+                (None, Some((left, events))) => {
+                    *left -= 1;
+                    events.push((event(entry), end));
+                }
+                (None, None) => {
+                    self.pending.push_back((event(entry), end));
+                    self.next = end;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Deals with the data file at `self.next` being gone. Returns whether
+    /// there is a file to read from now.
+    fn skip_missing(&mut self) -> Result<bool, Error> {
+        let ChangeCursor { fileid, pos } = self.next;
+        if pos == 0 {
+            // Nothing was read from it, so it is only a problem if a merge
+            // removed it, which `advance` finds out.
+            return self.advance(fileid);
+        }
+        // Fine if it was read to its end before a merge removed it.
+        let newer: Vec<u64> = utils::sorted_fileids(&self.ctx.path)?
+            .filter(|&id| id > fileid)
+            .collect();
+        for id in newer {
+            let merged = match merge::merged_files(&self.ctx.path, id) {
+                Ok(merged) => merged.unwrap_or_default(),
+                // Merged again since it was listed.
+                Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            if merged
+                .iter()
+                .any(|file| file.fileid == fileid && file.len <= pos)
+            {
+                return self.advance(fileid + 1);
+            }
+        }
+        Err(Error::CursorTooOld)
+    }
+
+    /// Moves on to the oldest data file from `fileid` on that the writer
+    /// wrote, failing if a merge removed one of those first. Returns whether
+    /// there is such a file yet.
+    fn advance(&mut self, fileid: u64) -> Result<bool, Error> {
+        loop {
+            match self.try_advance(fileid) {
+                // A merge removed a file after it was listed.
+                Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => continue,
+                result => return result,
+            }
+        }
+    }
+
+    fn try_advance(&mut self, fileid: u64) -> Result<bool, Error> {
+        let fileids: Vec<u64> = utils::sorted_fileids(&self.ctx.path)?.collect();
+        let mut next = None;
+        for &id in fileids.iter().filter(|&&id| id >= fileid) {
+            match merge::merged_files(&self.ctx.path, id)? {
+                // Merges only remove files once their outputs are in place,
+                // so a file listed here that is gone was never read.
+                Some(merged) => {
+                    if merged.iter().any(|file| {
+                        file.fileid >= fileid && fileids.binary_search(&file.fileid).is_err()
+                    }) {
+                        return Err(Error::CursorTooOld);
+                    }
+                }
+                None => {
+                    next.get_or_insert(id);
+                }
+            }
+        }
+        // Otherwise wait for the writer to start a file past every existing
+        // one.
+        let (fileid, found) = match next {
+            Some(id) => (id, true),
+            None => (
+                fileids.last().map_or(fileid, |&id| fileid.max(id + 1)),
+                false,
+            ),
+        };
+        self.next = ChangeCursor { fileid, pos: 0 };
+        Ok(found)
+    }
+}
+
+impl Iterator for Changes {
+    type Item = Result<(Event, ChangeCursor), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = loop {
+            match self.try_next() {
+                Ok(Some(change)) => break Ok(change),
+                Ok(None) => {}
+                Err(e) => break Err(e),
+            }
+            if let Err(e) = self.wait() {
+                break Err(e);
+            }
+        };
+        self.done = result.is_err();
+        Some(result)
+    }
+}
+
+fn event(entry: DataFileEntry) -> Event {
+    match entry.value {
+        Some(value) => Event::Put {
+            key: entry.key,
+            value,
+            tstamp: entry.tstamp,
+        },
+        None => Event::Delete {
+            key: entry.key,
+            tstamp: entry.tstamp,
+        },
+    }
+}
@@ -0,0 +1,134 @@
+use std::{io, thread::JoinHandle, time::Duration};
+
+use chrono::NaiveTime;
+use tokio::sync::broadcast;
+
+use crate::{task, Handle};
+
+/// Controls the background task that merges fragmented data files.
+///
+/// A sealed data file is merged once the share of dead entries in it reaches
+/// `min_fragmentation`, or once its dead entries take up `min_dead_bytes`.
+#[derive(Clone, Debug)]
+pub struct CompactionOptions {
+    enabled: bool,
+    interval: Duration,
+    min_fragmentation: f64,
+    min_dead_bytes: u64,
+    window: Option<(NaiveTime, NaiveTime)>,
+}
+
+impl Default for CompactionOptions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: Duration::from_secs(60),
+            min_fragmentation: 0.5,
+            min_dead_bytes: 8 * 1024 * 1024,
+            window: None,
+        }
+    }
+}
+
+impl CompactionOptions {
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// How often data file statistics are checked, at least every
+    /// millisecond. Expired keys are dropped from the keydir on the same
+    /// schedule, turning their values into dead bytes.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(task::MIN_PERIOD);
+        self
+    }
+
+    pub fn min_fragmentation(mut self, min_fragmentation: f64) -> Self {
+        self.min_fragmentation = min_fragmentation;
+        self
+    }
+
+    pub fn min_dead_bytes(mut self, min_dead_bytes: u64) -> Self {
+        self.min_dead_bytes = min_dead_bytes;
+        self
+    }
+
+    /// Only merge between `start` and `end` local time. The window may wrap
+    /// around midnight, e.g. from 22:00 to 04:00.
+    pub fn window(mut self, start: NaiveTime, end: NaiveTime) -> Self {
+        self.window = Some((start, end));
+        self
+    }
+
+    pub(super) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn in_window(&self, now: NaiveTime) -> bool {
+        match self.window {
+            None => true,
+            Some((start, end)) if start <= end => start <= now && now < end,
+            Some((start, end)) => start <= now || now < end,
+        }
+    }
+}
+
+pub(super) fn spawn(
+    handle: Handle,
+    options: CompactionOptions,
+    shutdown: broadcast::Receiver<()>,
+) -> io::Result<JoinHandle<()>> {
+    task::spawn_periodic("compaction", options.interval, shutdown, move || {
+        if let Err(e) = handle.expire() {
+            eprintln!("expiry failed - {e}");
+        }
+        if !options.in_window(chrono::Local::now().time()) {
+            return;
+        }
+        if let Err(e) = handle.compact(options.min_fragmentation, options.min_dead_bytes) {
+            eprintln!("compaction failed - {e}");
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+
+    use super::CompactionOptions;
+
+    fn time(hour: u32, min: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn no_window_is_always_open() {
+        let options = CompactionOptions::default();
+        assert!(options.in_window(time(0, 0)));
+        assert!(options.in_window(time(12, 30)));
+        assert!(options.in_window(time(23, 59)));
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let options = CompactionOptions::default().window(time(1, 0), time(5, 0));
+        assert!(!options.in_window(time(0, 59)));
+        assert!(options.in_window(time(1, 0)));
+        assert!(options.in_window(time(4, 59)));
+        assert!(!options.in_window(time(5, 0)));
+        assert!(!options.in_window(time(13, 0)));
+    }
+
+    #[test]
+    fn window_wrapping_around_midnight() {
+        let options = CompactionOptions::default().window(time(22, 0), time(4, 0));
+        assert!(!options.in_window(time(21, 59)));
+        assert!(options.in_window(time(22, 0)));
+        assert!(options.in_window(time(23, 59)));
+        assert!(options.in_window(time(0, 0)));
+        assert!(options.in_window(time(3, 59)));
+        assert!(!options.in_window(time(4, 0)));
+        assert!(!options.in_window(time(12, 0)));
+    }
+}
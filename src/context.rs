@@ -1,53 +1,339 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use bytes::Bytes;
 use crossbeam::atomic::AtomicCell;
-use crossbeam_skiplist::{map::Entry, SkipMap};
+use crossbeam_skiplist::{map::Entry, SkipMap, SkipSet};
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::broadcast;
+
+use crate::{
+    keyspace::Registry, log::KeyspaceStatistics, metrics::Metrics, subscription::Event, utils,
+};
 
 #[derive(Debug)]
 pub(super) struct Context {
     pub path: PathBuf,
-    keydir: SkipMap<Bytes, KeyDirEntry>,
+    pub max_file_size: u64,
+    pub metrics: Metrics,
+    /// The named keyspaces, locked while one is created or dropped.
+    pub keyspaces: Mutex<Registry>,
+    keydirs: Keydirs,
     closed: AtomicCell<bool>,
+    epoch: AtomicCell<u64>,
+    pins: Mutex<Pins>,
+    /// Feeds subscriptions with the writes made through this store, along
+    /// with the id of their keyspace, dropped on close so that subscribers
+    /// learn about it.
+    events: Mutex<Option<broadcast::Sender<(u32, Event)>>>,
+}
+
+/// Data files that snapshots still read from.
+#[derive(Debug, Default)]
+struct Pins {
+    /// Number of snapshots holding each file.
+    counts: HashMap<u64, usize>,
+    /// Pinned files a merge has already replaced, which are deleted once the
+    /// last snapshot holding them is dropped.
+    retired: Vec<u64>,
 }
 
 impl Context {
-    pub(super) fn new<P: AsRef<Path>>(path: P, keydir: SkipMap<Bytes, KeyDirEntry>) -> Self {
+    pub(super) fn new<P: AsRef<Path>>(
+        path: P,
+        max_file_size: u64,
+        subscription_capacity: usize,
+        keydirs: Keydirs,
+        keyspaces: Registry,
+    ) -> Self {
+        let (events, _) = broadcast::channel(subscription_capacity.max(1));
         Self {
             path: path.as_ref().to_path_buf(),
-            keydir,
+            max_file_size,
+            metrics: Metrics::default(),
+            keyspaces: Mutex::new(keyspaces),
+            keydirs,
             closed: AtomicCell::new(false),
+            epoch: AtomicCell::new(0),
+            pins: Mutex::default(),
+            events: Mutex::new(Some(events)),
+        }
+    }
+
+    pub(super) fn keydirs(&self) -> &Keydirs {
+        &self.keydirs
+    }
+
+    /// Bumped every time data files are removed, so that cached readers know
+    /// their open files may be gone.
+    pub(super) fn epoch(&self) -> u64 {
+        self.epoch.load()
+    }
+
+    pub(super) fn bump_epoch(&self) {
+        self.epoch.fetch_add(1);
+    }
+
+    pub(super) fn pin(&self, fileids: &[u64]) {
+        let mut pins = self.pins.lock();
+        for &fileid in fileids {
+            *pins.counts.entry(fileid).or_default() += 1;
+        }
+    }
+
+    pub(super) fn unpin(&self, fileids: &[u64]) -> io::Result<()> {
+        let mut pins = self.pins.lock();
+        for fileid in fileids {
+            let Some(count) = pins.counts.get_mut(fileid) else {
+                continue;
+            };
+            *count -= 1;
+            if *count == 0 {
+                pins.counts.remove(fileid);
+            }
+        }
+        let (unpinned, retired) = std::mem::take(&mut pins.retired)
+            .into_iter()
+            .partition(|fileid| !pins.counts.contains_key(fileid));
+        pins.retired = retired;
+        for fileid in unpinned {
+            utils::remove_if_exists(utils::tmpfile_name(utils::datafile_name(
+                &self.path, fileid,
+            )))?;
+        }
+        Ok(())
+    }
+
+    /// Removes a data file a merge has replaced, along with its hint file. A
+    /// file pinned by a snapshot is only moved aside under a temporary name,
+    /// which recovery discards, and removed once it is unpinned.
+    pub(super) fn retire(&self, fileid: u64) -> io::Result<()> {
+        let datafile = utils::datafile_name(&self.path, fileid);
+        utils::remove_if_exists(utils::hintfile_name(&self.path, fileid))?;
+        let mut pins = self.pins.lock();
+        if pins.counts.contains_key(&fileid) {
+            fs::rename(&datafile, utils::tmpfile_name(&datafile))?;
+            pins.retired.push(fileid);
+            Ok(())
+        } else {
+            utils::remove_if_exists(datafile)
+        }
+    }
+
+    /// Sends the event built by `event`, a write to keyspace `keyspace`, to
+    /// every subscription, if there is any.
+    pub(super) fn publish(&self, keyspace: u32, event: impl FnOnce() -> Event) {
+        if let Some(events) = &*self.events.lock() {
+            if events.receiver_count() > 0 {
+                let _ = events.send((keyspace, event()));
+            }
+        }
+    }
+
+    pub(super) fn subscribe(&self) -> Option<broadcast::Receiver<(u32, Event)>> {
+        self.events
+            .lock()
+            .as_ref()
+            .map(broadcast::Sender::subscribe)
+    }
+
+    pub(super) fn close(&self) {
+        self.closed.store(true);
+        self.events.lock().take();
+    }
+
+    pub(super) fn is_closed(&self) -> bool {
+        self.closed.load()
+    }
+}
+
+/// The keydirs of every keyspace, by id. The default keyspace has id 0.
+#[derive(Debug, Default)]
+pub(super) struct Keydirs(RwLock<HashMap<u32, Arc<Keydir>>>);
+
+impl Keydirs {
+    /// The keydir of keyspace `id`, created empty the first time it is
+    /// asked for.
+    pub(super) fn get(&self, id: u32) -> Arc<Keydir> {
+        if let Some(keydir) = self.0.read().get(&id) {
+            return keydir.clone();
+        }
+        self.0
+            .write()
+            .entry(id)
+            .or_insert_with(|| Arc::new(Keydir::new(id)))
+            .clone()
+    }
+
+    /// The keydirs of the keyspaces that were not dropped.
+    pub(super) fn live(&self) -> Vec<Arc<Keydir>> {
+        self.0
+            .read()
+            .values()
+            .filter(|keydir| !keydir.is_dropped())
+            .cloned()
+            .collect()
+    }
+
+    pub(super) fn is_dropped(&self, id: u32) -> bool {
+        self.0
+            .read()
+            .get(&id)
+            .is_some_and(|keydir| keydir.is_dropped())
+    }
+
+    /// Marks keyspace `id` as dropped, leaving an empty keydir in place of
+    /// its own. Its entries are freed along with the last handle to it, so
+    /// this takes the same time no matter how many keys it holds.
+    pub(super) fn drop_keyspace(&self, id: u32) {
+        let dropped = Keydir::new(id);
+        dropped.dropped.store(true);
+        if let Some(keydir) = self.0.write().insert(id, Arc::new(dropped)) {
+            keydir.dropped.store(true);
+        }
+    }
+}
+
+/// Maps every live key of one keyspace to where its value is, shared by the
+/// writer, which applies write batches to it in one step, and the readers.
+#[derive(Debug)]
+pub(super) struct Keydir {
+    id: u32,
+    entries: SkipMap<Bytes, KeyDirEntry>,
+    /// The keys whose values have a TTL, by when they expire, so that
+    /// expired keys are found without going through all of them.
+    deadlines: SkipSet<(i64, Bytes)>,
+    /// Taken exclusively while a write batch is applied to the keydir and
+    /// shared by lookups, so that readers see all of a batch or none of it.
+    batch_lock: RwLock<()>,
+    /// Set once the keyspace is dropped, after which the keydir reads as
+    /// empty and must no longer be written.
+    dropped: AtomicCell<bool>,
+}
+
+impl Keydir {
+    fn new(id: u32) -> Self {
+        Self {
+            id,
+            entries: SkipMap::new(),
+            deadlines: SkipSet::new(),
+            batch_lock: RwLock::new(()),
+            dropped: AtomicCell::new(false),
+        }
+    }
+
+    /// Id of the keyspace.
+    pub(super) fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub(super) fn is_dropped(&self) -> bool {
+        self.dropped.load()
+    }
+
+    /// Number of keys, none once the keyspace is dropped.
+    pub(super) fn len(&self) -> u64 {
+        if self.is_dropped() {
+            0
+        } else {
+            self.entries.len() as u64
         }
     }
 
-    pub(super) fn keydir_set(
+    /// The entries, as they are in the middle of a write batch too.
+    pub(super) fn entries(&self) -> &SkipMap<Bytes, KeyDirEntry> {
+        &self.entries
+    }
+
+    pub(super) fn insert(
         &self,
         key: Bytes,
         keydir_entry: KeyDirEntry,
     ) -> Option<Entry<'_, Bytes, KeyDirEntry>> {
-        let prev_entry = self.keydir.get(&key);
-        self.keydir.insert(key, keydir_entry);
+        let prev_entry = self.entries.get(&key);
+        if let Some(expires) = prev_entry.as_ref().and_then(|entry| entry.value().expires) {
+            self.deadlines.remove(&(expires, key.clone()));
+        }
+        if let Some(expires) = keydir_entry.expires {
+            self.deadlines.insert((expires, key.clone()));
+        }
+        self.entries.insert(key, keydir_entry);
         prev_entry
     }
 
-    pub(super) fn get_keydir(&self) -> &SkipMap<Bytes, KeyDirEntry> {
-        &self.keydir
+    pub(super) fn remove(&self, key: &[u8]) -> Option<Entry<'_, Bytes, KeyDirEntry>> {
+        let entry = self.entries.remove(key)?;
+        if let Some(expires) = entry.value().expires {
+            self.deadlines.remove(&(expires, entry.key().clone()));
+        }
+        Some(entry)
     }
 
-    pub(super) fn close(&self) {
-        self.closed.store(true)
+    /// The keys whose values expired by `now`, along with where those values
+    /// are, found without going through the keys that have not.
+    pub(super) fn expired(&self, now: i64) -> Vec<(Bytes, u64, u64)> {
+        self.deadlines
+            .iter()
+            .take_while(|deadline| utils::is_expired(Some(deadline.0), now))
+            .filter_map(|deadline| {
+                let (_, key) = &*deadline;
+                let entry = self.entries.get(key)?;
+                Some((key.clone(), entry.value().fileid, entry.value().pos))
+            })
+            .collect()
     }
 
-    pub(super) fn is_closed(&self) -> bool {
-        self.closed.load()
+    /// Looks `key` up once no write batch is half applied. Finds nothing
+    /// once the keyspace is dropped.
+    pub(super) fn get(&self, key: &[u8]) -> Option<Entry<'_, Bytes, KeyDirEntry>> {
+        if self.is_dropped() {
+            return None;
+        }
+        let _guard = self.batch_lock.read();
+        self.entries.get(key)
+    }
+
+    /// Runs `f` on the entries once no write batch is half applied.
+    pub(super) fn with<T>(&self, f: impl FnOnce(&SkipMap<Bytes, KeyDirEntry>) -> T) -> T {
+        let _guard = self.batch_lock.read();
+        f(&self.entries)
+    }
+
+    /// Drops `key` if it still points at the expired value at `pos` in
+    /// `fileid`, counting the value as dead in `stats`.
+    pub(super) fn expire(&self, key: &[u8], fileid: u64, pos: u64, stats: &mut KeyspaceStatistics) {
+        if self.is_dropped() {
+            return;
+        }
+        let Some(entry) = self.entries.get(key) else {
+            return;
+        };
+        if entry.value().fileid == fileid && entry.value().pos == pos && entry.remove() {
+            if let Some(expires) = entry.value().expires {
+                self.deadlines.remove(&(expires, entry.key().clone()));
+            }
+            stats.file(self.id, fileid).overwrite(entry.value().len);
+        }
+    }
+
+    /// Runs `f`, which applies a write batch to the entries, while no lookup
+    /// can observe it.
+    pub(super) fn apply_batch<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _guard = self.batch_lock.write();
+        f()
     }
 }
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(super) struct KeyDirEntry {
     pub(super) fileid: u64,
     pub(super) len: u64,
     pub(super) pos: u64,
     pub(super) tstamp: i64,
+    pub(super) expires: Option<i64>,
 }
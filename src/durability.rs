@@ -0,0 +1,187 @@
+use std::{io, thread::JoinHandle, time::Duration};
+
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use tokio::sync::broadcast;
+
+use crate::{task, writer::Writer, Error, Handle};
+
+/// When writes are flushed to disk with `fsync`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync every write before it is acknowledged.
+    Always,
+    /// Sync from a background task at a fixed interval.
+    Interval(Duration),
+    /// Sync once this many bytes were written since the last sync.
+    Bytes(u64),
+    /// Leave flushing to the operating system.
+    #[default]
+    Never,
+}
+
+/// Lets concurrent writers share one `fsync`.
+///
+/// Every append gets a sequence number. A writer waiting for its append to be
+/// durable either finds a sync already covering it, or becomes the leader and
+/// syncs everything appended so far without holding the writer lock, while
+/// later writers queue up behind it for the next round.
+#[derive(Debug, Default)]
+pub(super) struct GroupCommit {
+    state: Mutex<CommitState>,
+    synced: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct CommitState {
+    synced_seq: u64,
+    syncing: bool,
+    /// The last sync that failed, with the sequence number it was meant to
+    /// cover. Every append up to that number fails along with it.
+    failed: Option<(u64, io::ErrorKind, String)>,
+}
+
+impl GroupCommit {
+    /// Blocks until the append numbered `seq` is on disk.
+    pub(super) fn wait(&self, seq: u64, writer: &Mutex<Writer>) -> Result<(), Error> {
+        self.wait_with(
+            seq,
+            || writer.lock().sync_target(),
+            |target| {
+                target.file.sync_data()?;
+                writer.lock().mark_synced(target.fileid, target.len);
+                Ok(())
+            },
+        )
+    }
+
+    /// Blocks until the append numbered `seq` is covered by `sync`, which
+    /// flushes what `prepare` returns along with the last sequence number.
+    fn wait_with<T>(
+        &self,
+        seq: u64,
+        prepare: impl FnOnce() -> io::Result<(u64, T)>,
+        sync: impl FnOnce(T) -> io::Result<()>,
+    ) -> Result<(), Error> {
+        let mut prepare = Some(prepare);
+        let mut sync = Some(sync);
+        let mut state = self.state.lock();
+        loop {
+            if state.synced_seq >= seq {
+                return Ok(());
+            }
+            if let Some((failed_seq, kind, message)) = &state.failed {
+                if *failed_seq >= seq {
+                    return Err(io::Error::new(*kind, message.clone()).into());
+                }
+            }
+            if state.syncing {
+                self.synced.wait(&mut state);
+                continue;
+            }
+            // A waiter leads at most one round, since the sequence number a
+            // sync targets is never older than the appends waiting for it.
+            let (Some(prepare), Some(sync)) = (prepare.take(), sync.take()) else {
+                unreachable!("a sync covers the waiter that led it");
+            };
+
+            state.syncing = true;
+            let result = MutexGuard::unlocked(&mut state, || {
+                let (target, flush) = prepare().map_err(|e| (None, e))?;
+                sync(flush).map_err(|e| (Some(target), e))?;
+                Ok(target)
+            });
+            state.syncing = false;
+            self.synced.notify_all();
+            match result {
+                Ok(target) => state.synced_seq = state.synced_seq.max(target),
+                Err((target, e)) => {
+                    if let Some(target) = target {
+                        state.failed = Some((target, e.kind(), e.to_string()));
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+}
+
+pub(super) fn spawn(
+    handle: Handle,
+    interval: Duration,
+    shutdown: broadcast::Receiver<()>,
+) -> io::Result<JoinHandle<()>> {
+    task::spawn_periodic("sync", interval, shutdown, move || {
+        if let Err(e) = handle.sync() {
+            eprintln!("sync failed - {e}");
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Barrier,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::GroupCommit;
+
+    const WAITERS: u64 = 8;
+
+    /// Lets `WAITERS` threads append and then wait on `commit` together,
+    /// each sync taking long enough for all of them to queue up behind it.
+    /// Returns how many syncs ran and what each waiter got.
+    fn run(commit: &GroupCommit, fail: bool) -> (u64, Vec<Result<(), String>>) {
+        let seq = AtomicU64::new(0);
+        let syncs = AtomicU64::new(0);
+        let barrier = Barrier::new(WAITERS as usize);
+        let results = thread::scope(|s| {
+            let waiters: Vec<_> = (0..WAITERS)
+                .map(|_| {
+                    s.spawn(|| {
+                        let appended = seq.fetch_add(1, Ordering::SeqCst) + 1;
+                        barrier.wait();
+                        commit.wait_with(
+                            appended,
+                            || Ok((seq.load(Ordering::SeqCst), ())),
+                            |()| {
+                                syncs.fetch_add(1, Ordering::SeqCst);
+                                thread::sleep(Duration::from_millis(50));
+                                match fail {
+                                    true => Err(io::Error::other("disk on fire")),
+                                    false => Ok(()),
+                                }
+                            },
+                        )
+                    })
+                })
+                .collect();
+            waiters
+                .into_iter()
+                .map(|waiter| waiter.join().unwrap().map_err(|e| e.to_string()))
+                .collect()
+        });
+        (syncs.load(Ordering::SeqCst), results)
+    }
+
+    #[test]
+    fn concurrent_waiters_share_one_sync() {
+        let (syncs, results) = run(&GroupCommit::default(), false);
+        assert_eq!(syncs, 1);
+        assert!(results.iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn failed_sync_fails_every_waiter_it_covered() {
+        let (syncs, results) = run(&GroupCommit::default(), true);
+        assert_eq!(syncs, 1);
+        for result in results {
+            assert!(result.unwrap_err().contains("disk on fire"));
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::{io, sync::Arc, thread::JoinHandle, time::Duration};
+
+use tokio::sync::broadcast;
+
+use crate::{
+    context::{Context, KeyDirEntry},
+    keyspace::Registry,
+    log::KeyspaceStatistics,
+    populate_keydir, populate_keydir_with_datafile, rebuild_storage, task, utils, Error, Handle,
+    Storage, TornTail,
+};
+
+/// Keeps the keydir of a read-only store up to date with the data files the
+/// writing process appends to.
+#[derive(Debug)]
+pub(super) struct Follower {
+    ctx: Arc<Context>,
+    stats: KeyspaceStatistics,
+    fileids: Vec<u64>,
+    newest_len: u64,
+}
+
+impl Follower {
+    pub(super) fn new(ctx: Arc<Context>, storage: Storage) -> Self {
+        Self {
+            ctx,
+            stats: storage.stats,
+            fileids: storage.fileids,
+            newest_len: storage.newest_len,
+        }
+    }
+
+    pub(super) fn get_stats(&self) -> &KeyspaceStatistics {
+        &self.stats
+    }
+
+    pub(super) fn get_stats_mut(&mut self) -> &mut KeyspaceStatistics {
+        &mut self.stats
+    }
+
+    /// Id of the newest data file replayed and how much of it was.
+    pub(super) fn newest(&self) -> Option<(u64, u64)> {
+        Some((*self.fileids.last()?, self.newest_len))
+    }
+
+    /// Replays whatever the writer appended since the last refresh.
+    pub(super) fn refresh(&mut self) -> Result<(), Error> {
+        match self.tail() {
+            // The writer removed an empty active data file or a merge removed
+            // a sealed one while it was being replayed.
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => self.reload(),
+            result => result,
+        }
+    }
+
+    fn tail(&mut self) -> Result<(), Error> {
+        let fileids: Vec<u64> = utils::sorted_fileids(&self.ctx.path)?.collect();
+        let newest = self.fileids.last().copied();
+        let (known, new) = fileids.split_at(
+            fileids
+                .iter()
+                .take_while(|&&id| newest.is_some_and(|newest| id <= newest))
+                .count(),
+        );
+        // A merge removed files or added some that sort before the newest one
+        // already replayed, so replaying on top of the keydir would be wrong.
+        if known != self.fileids {
+            return self.reload();
+        }
+
+        // Keyspaces dropped since the last refresh are dropped before the
+        // records appended to them are replayed, which then count as dead.
+        let keyspaces = Registry::load(&self.ctx.path)?;
+        for id in keyspaces.dropped() {
+            if !self.ctx.keydirs().is_dropped(id) {
+                self.ctx.keydirs().drop_keyspace(id);
+                self.stats.drop_keyspace(id);
+            }
+        }
+        *self.ctx.keyspaces.lock() = keyspaces;
+
+        let keydirs = self.ctx.keydirs();
+        if let Some(newest) = newest {
+            let torn_tail = new.is_empty().then_some(TornTail::Ignore);
+            self.newest_len = populate_keydir_with_datafile(
+                &self.ctx.path,
+                newest,
+                self.newest_len,
+                keydirs,
+                &mut self.stats,
+                torn_tail,
+            )?;
+        }
+        for (i, &fileid) in new.iter().enumerate() {
+            let torn_tail = (i + 1 == new.len()).then_some(TornTail::Ignore);
+            self.newest_len =
+                populate_keydir(&self.ctx.path, fileid, keydirs, &mut self.stats, torn_tail)?;
+            self.fileids.push(fileid);
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the keydirs from scratch and swaps them in key by key. New
+    /// entries go in before stale keys are removed, so a concurrent `get`
+    /// never misses a key that is live both before and after.
+    fn reload(&mut self) -> Result<(), Error> {
+        let storage = loop {
+            match rebuild_storage(&self.ctx.path, TornTail::Ignore) {
+                // A file was merged away while it was being replayed.
+                Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => continue,
+                result => break result?,
+            }
+        };
+
+        for id in storage.keyspaces.dropped() {
+            self.ctx.keydirs().drop_keyspace(id);
+        }
+        for rebuilt in storage.keydirs.live() {
+            let keydir = self.ctx.keydirs().get(rebuilt.id());
+            for entry in rebuilt.entries().iter() {
+                let keydir_entry = entry.value();
+                keydir.insert(
+                    entry.key().clone(),
+                    KeyDirEntry {
+                        fileid: keydir_entry.fileid,
+                        len: keydir_entry.len,
+                        pos: keydir_entry.pos,
+                        tstamp: keydir_entry.tstamp,
+                        expires: keydir_entry.expires,
+                    },
+                );
+            }
+        }
+        for keydir in self.ctx.keydirs().live() {
+            let rebuilt = storage.keydirs.get(keydir.id());
+            for entry in keydir.entries().iter() {
+                if !rebuilt.entries().contains_key(entry.key()) {
+                    keydir.remove(entry.key());
+                }
+            }
+        }
+        self.ctx.bump_epoch();
+
+        *self.ctx.keyspaces.lock() = storage.keyspaces;
+        self.stats = storage.stats;
+        self.fileids = storage.fileids;
+        self.newest_len = storage.newest_len;
+        Ok(())
+    }
+}
+
+pub(super) fn spawn(
+    handle: Handle,
+    interval: Duration,
+    shutdown: broadcast::Receiver<()>,
+) -> io::Result<JoinHandle<()>> {
+    task::spawn_periodic("refresh", interval, shutdown, move || {
+        if let Err(e) = handle.refresh() {
+            eprintln!("refresh failed - {e}");
+        }
+    })
+}
@@ -0,0 +1,117 @@
+use std::{io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    log::{self, LogIterator},
+    utils, Error,
+};
+
+/// A keyspace as recorded in the registry. Ids are never reused, so that the
+/// records a dropped keyspace left in the data files stay dead even after a
+/// keyspace of the same name is created again. They start at 1, the records
+/// of the default keyspace carrying 0.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct KeyspaceEntry {
+    name: String,
+    id: u32,
+    dropped: bool,
+}
+
+/// The named keyspaces of a store, kept in a file of their own next to the
+/// data files and rewritten whenever one is created or dropped.
+#[derive(Debug, Default)]
+pub(super) struct Registry {
+    keyspaces: Vec<KeyspaceEntry>,
+}
+
+impl Registry {
+    pub(super) fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = match log::open(utils::keyspaces_name(path)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut iter = LogIterator::new(file, 0)?;
+        let mut keyspaces = Vec::new();
+        while let Some((_, entry)) = iter.next::<KeyspaceEntry>()? {
+            keyspaces.push(entry);
+        }
+        Ok(Self { keyspaces })
+    }
+
+    /// Id of the live keyspace called `name`.
+    pub(super) fn get(&self, name: &str) -> Option<u32> {
+        self.keyspaces
+            .iter()
+            .find(|keyspace| !keyspace.dropped && keyspace.name == name)
+            .map(|keyspace| keyspace.id)
+    }
+
+    /// Names of the live keyspaces, in the order they were created.
+    pub(super) fn names(&self) -> Vec<String> {
+        self.keyspaces
+            .iter()
+            .filter(|keyspace| !keyspace.dropped)
+            .map(|keyspace| keyspace.name.clone())
+            .collect()
+    }
+
+    /// Records a new keyspace called `name` in the registry at `path` and
+    /// returns its id.
+    pub(super) fn create<P: AsRef<Path>>(&mut self, path: P, name: &str) -> Result<u32, Error> {
+        let id = match self.keyspaces.iter().map(|keyspace| keyspace.id).max() {
+            Some(id) => id
+                .checked_add(1)
+                .ok_or_else(|| io::Error::other("out of keyspace ids"))?,
+            None => 1,
+        };
+        self.keyspaces.push(KeyspaceEntry {
+            name: name.to_string(),
+            id,
+            dropped: false,
+        });
+        self.save(path)?;
+        Ok(id)
+    }
+
+    /// Marks the keyspace called `name` as dropped in the registry at `path`
+    /// and returns its id, `None` if there is no such keyspace.
+    pub(super) fn remove<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        name: &str,
+    ) -> Result<Option<u32>, Error> {
+        let Some(keyspace) = self
+            .keyspaces
+            .iter_mut()
+            .find(|keyspace| !keyspace.dropped && keyspace.name == name)
+        else {
+            return Ok(None);
+        };
+        keyspace.dropped = true;
+        let id = keyspace.id;
+        self.save(path)?;
+        Ok(Some(id))
+    }
+
+    /// Ids of the keyspaces that were dropped.
+    pub(super) fn dropped(&self) -> impl Iterator<Item = u32> + '_ {
+        self.keyspaces
+            .iter()
+            .filter(|keyspace| keyspace.dropped)
+            .map(|keyspace| keyspace.id)
+    }
+
+    /// Ids of the live keyspaces.
+    pub(super) fn live(&self) -> impl Iterator<Item = u32> + '_ {
+        self.keyspaces
+            .iter()
+            .filter(|keyspace| !keyspace.dropped)
+            .map(|keyspace| keyspace.id)
+    }
+
+    fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        log::write_atomic(utils::keyspaces_name(path), &self.keyspaces)
+    }
+}
@@ -1,28 +1,70 @@
+mod asynchronous;
+mod backup;
+mod batch;
 mod bufio;
+mod changes;
+mod compaction;
 mod context;
+mod durability;
+mod follower;
+mod keyspace;
+mod lock;
 mod log;
+mod merge;
+mod metrics;
+mod options;
 mod reader;
+mod scan;
+mod snapshot;
+mod stats;
+mod subscription;
+mod task;
+mod upgrade;
 mod utils;
 mod writer;
 
-use std::{cell::RefCell, collections::HashMap, io, path::Path, sync::Arc};
+use std::{
+    cell::RefCell,
+    fs,
+    future::Future,
+    io,
+    num::NonZeroUsize,
+    ops::RangeBounds,
+    path::Path,
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
 use bytes::Bytes;
-use context::KeyDirEntry;
+use context::{KeyDirEntry, Keydir, Keydirs};
 use crossbeam::{queue::ArrayQueue, utils::Backoff};
-use crossbeam_skiplist::SkipMap;
-use log::{LogIterator, LogStatistics};
-use parking_lot::Mutex;
+use log::{KeyspaceStatistics, LogIndex, LogIterator};
+use parking_lot::{Mutex, MutexGuard};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, Semaphore, SemaphorePermit};
 
 use crate::log::{LogDir, LogWriter};
 
-use self::{context::Context, reader::Reader, writer::Writer};
+pub use self::{
+    asynchronous::AsyncHandle,
+    batch::WriteBatch,
+    changes::{ChangeCursor, Changes},
+    compaction::CompactionOptions,
+    durability::SyncPolicy,
+    metrics::Metrics,
+    options::Options,
+    scan::{Keys, Range},
+    snapshot::{Snapshot, SnapshotRange},
+    stats::{FileStats, Stats},
+    subscription::{Event, Subscription},
+};
 
-const CONCURRENCY: usize = 4;
-const READER_CACHE_SIZE: usize = 16;
+use self::{
+    context::Context, durability::GroupCommit, follower::Follower, keyspace::Registry,
+    lock::DirLock, merge::Merger, metrics::Op, reader::Reader, scan::Cursor, writer::Writer,
+};
 
 pub trait KeyValueStorage: Clone + Send + 'static {
     type Error: std::error::Error + Send + Sync;
@@ -32,58 +74,216 @@ pub trait KeyValueStorage: Clone + Send + 'static {
     fn del(&self, key: Bytes) -> Result<bool, Self::Error>;
 }
 
+/// The non-blocking counterpart of [`KeyValueStorage`], for use from async
+/// tasks.
+pub trait AsyncKeyValueStorage: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync;
+
+    fn set(&self, key: Bytes, value: Bytes)
+        -> impl Future<Output = Result<(), Self::Error>> + Send;
+    fn get(&self, key: Bytes) -> impl Future<Output = Result<Option<Bytes>, Self::Error>> + Send;
+    fn del(&self, key: Bytes) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+}
+
 #[allow(dead_code)]
 pub struct Bitcask {
     handle: Handle,
-    // 
+    //
     shutdown: broadcast::Sender<()>,
+    tasks: Vec<JoinHandle<()>>,
 }
 
 #[allow(dead_code)]
 impl Bitcask {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let (keydir, stats, active_fileid) = rebuild_storage(&path)?;
+        Self::open_with(path, Options::default())
+    }
+
+    pub fn open_with<P: AsRef<Path>>(path: P, options: Options) -> Result<Self, Error> {
+        if options.create_if_missing && !options.read_only {
+            fs::create_dir_all(&path)?;
+        }
+        if options.error_if_exists && utils::sorted_fileids(&path)?.next().is_some() {
+            return Err(Error::AlreadyExists);
+        }
+        let (lock, torn_tail) = if options.read_only {
+            (None, TornTail::Ignore)
+        } else {
+            let lock = DirLock::acquire(&path)?;
+            utils::remove_tmpfiles(&path)?;
+            (Some(lock), TornTail::Truncate)
+        };
+        let mut storage = rebuild_storage(&path, torn_tail)?;
+        let active_fileid = storage.active_fileid();
 
-        let ctx = Arc::new(Context::new(&path, keydir));
-        let readers = Arc::new(ArrayQueue::new(CONCURRENCY));
+        let keydirs = std::mem::take(&mut storage.keydirs);
+        let keyspaces = std::mem::take(&mut storage.keyspaces);
+        let ctx = Arc::new(Context::new(
+            &path,
+            options.max_file_size,
+            options.subscription_capacity,
+            keydirs,
+            keyspaces,
+        ));
+        let cache_size = NonZeroUsize::new(options.reader_cache_size).unwrap_or(NonZeroUsize::MIN);
+        let readers = Arc::new(ArrayQueue::new(options.readers.max(1)));
 
         for _ in 0..readers.capacity() {
             readers
                 .push(Reader::new(
                     ctx.clone(),
-                    RefCell::new(LogDir::new(READER_CACHE_SIZE.try_into().unwrap())),
+                    RefCell::new(LogDir::new(cache_size)),
                 ))
                 .expect("error");
         }
 
-        let writer = Arc::new(Mutex::new(Writer::new(
+        let role = match lock {
+            None => Role::Follower(Arc::new(Mutex::new(Follower::new(ctx.clone(), storage)))),
+            Some(lock) => Role::Writer(Arc::new(Mutex::new(Writer::new(
+                ctx.clone(),
+                RefCell::new(LogDir::new(cache_size)),
+                LogWriter::new(log::create(utils::datafile_name(&path, active_fileid))?)?,
+                storage.stats,
+                active_fileid,
+                options.sync_policy,
+                lock,
+            )))),
+        };
+
+        let merger = Arc::new(Mutex::new(Merger::new(
             ctx.clone(),
-            RefCell::new(LogDir::new(READER_CACHE_SIZE.try_into().unwrap())),
-            LogWriter::new(log::create(utils::datafile_name(&path, active_fileid))?)?,
-            stats,
-            active_fileid,
-            0,
+            LogDir::new(cache_size),
         )));
 
         let handle = Handle {
+            keydir: ctx.keydirs().get(0),
             ctx,
-            writer,
+            role,
+            reader_permits: Arc::new(Semaphore::new(readers.capacity())),
             readers,
+            merger,
+            sync_policy: options.sync_policy,
+            group_commit: Arc::default(),
         };
 
         let (shutdown, _) = broadcast::channel(1);
-        let bitcask = Self { handle, shutdown };
+        let mut tasks = Vec::new();
+        if options.compaction.is_enabled() && !options.read_only {
+            tasks.push(compaction::spawn(
+                handle.clone(),
+                options.compaction,
+                shutdown.subscribe(),
+            )?);
+        }
+        if let (SyncPolicy::Interval(interval), false) = (options.sync_policy, options.read_only) {
+            tasks.push(durability::spawn(
+                handle.clone(),
+                interval,
+                shutdown.subscribe(),
+            )?);
+        }
+        if let (Some(interval), true) = (options.refresh_interval, options.read_only) {
+            tasks.push(follower::spawn(
+                handle.clone(),
+                interval,
+                shutdown.subscribe(),
+            )?);
+        }
+        let bitcask = Self {
+            handle,
+            shutdown,
+            tasks,
+        };
 
         Ok(bitcask)
     }
 
+    /// Rebuilds a store in `dest` from a full checkpoint followed by the
+    /// incremental checkpoints taken on top of it, oldest first.
+    pub fn restore<P, Q>(backups: &[P], dest: Q) -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        backup::restore(backups, dest)
+    }
+
+    /// Converts a store written before its files carried a format version to
+    /// the current format, in place. Stores in that format fail to open with
+    /// [`Error::UnsupportedVersion`] until they are upgraded.
+    ///
+    /// Data files are rewritten one at a time through a temporary copy, so
+    /// this needs as much free space as the largest of them. Files already in
+    /// the current format are left alone, which makes it safe to run again
+    /// after an interruption.
+    pub fn upgrade<P: AsRef<Path>>(path: P) -> Result<(), Error> {
+        upgrade::upgrade(path)
+    }
+
     pub fn get_handle(&self) -> Handle {
         self.handle.clone()
     }
+
+    pub fn get_async_handle(&self) -> AsyncHandle {
+        AsyncHandle::new(self.handle.clone())
+    }
+
+    /// Returns a handle to the keyspace called `name`, creating it unless
+    /// the store is read-only. A keyspace keeps its keys apart from those of
+    /// the default keyspace, which [`get_handle`](Self::get_handle) serves,
+    /// and of every other keyspace, while sharing the data files, the writer
+    /// and the readers of the store.
+    ///
+    /// Reads, writes, scans, snapshots, subscriptions and change readers of
+    /// the handle only see the keys of its keyspace, and [`stats`](Handle::stats)
+    /// only counts those. Merges, checkpoints, syncs and metrics still cover
+    /// the whole store.
+    pub fn keyspace(&self, name: &str) -> Result<Handle, Error> {
+        let ctx = &self.handle.ctx;
+        let mut keyspaces = ctx.keyspaces.lock();
+        let id = match keyspaces.get(name) {
+            Some(id) => id,
+            None => {
+                self.handle.writer()?;
+                keyspaces.create(&ctx.path, name)?
+            }
+        };
+        Ok(Handle {
+            keydir: ctx.keydirs().get(id),
+            ..self.handle.clone()
+        })
+    }
+
+    /// Drops the keyspace called `name` along with all of its keys. Returns
+    /// whether there was such a keyspace.
+    ///
+    /// Nothing is rewritten and the keys are not visited: the keyspace is
+    /// marked as dropped and the space its records take in the data files is
+    /// reclaimed by later merges. Handles to the keyspace read it as empty
+    /// and fail to write with [`Error::KeyspaceDropped`]; a keyspace created
+    /// under the same name afterwards starts out empty.
+    pub fn drop_keyspace(&self, name: &str) -> Result<bool, Error> {
+        let ctx = &self.handle.ctx;
+        let mut writer = self.handle.lock_writer()?;
+        let Some(id) = ctx.keyspaces.lock().remove(&ctx.path, name)? else {
+            return Ok(false);
+        };
+        writer.drop_keyspace(id);
+        Ok(true)
+    }
+
+    /// Names of the keyspaces of the store, in the order they were created.
+    pub fn keyspaces(&self) -> Vec<String> {
+        self.handle.ctx.keyspaces.lock().names()
+    }
 }
 
 impl Drop for Bitcask {
     fn drop(&mut self) {
+        let _ = self.shutdown.send(());
+        for task in self.tasks.drain(..) {
+            let _ = task.join();
+        }
         self.handle.close();
     }
 }
@@ -91,51 +291,364 @@ impl Drop for Bitcask {
 #[derive(Clone, Debug)]
 pub struct Handle {
     ctx: Arc<Context>,
-    writer: Arc<Mutex<Writer>>,
+    /// The keydir of the keyspace this handle reads and writes.
+    keydir: Arc<Keydir>,
+    role: Role,
     readers: Arc<ArrayQueue<Reader>>,
+    /// One permit per reader, so that sync and async callers alike only take
+    /// a reader from the pool once one is free.
+    reader_permits: Arc<Semaphore>,
+    merger: Arc<Mutex<Merger>>,
+    sync_policy: SyncPolicy,
+    group_commit: Arc<GroupCommit>,
+}
+
+/// Whether a handle writes to the store or follows another process that
+/// does, which is the case for a store opened read-only.
+#[derive(Clone, Debug)]
+enum Role {
+    Writer(Arc<Mutex<Writer>>),
+    Follower(Arc<Mutex<Follower>>),
+}
+
+impl Role {
+    /// Runs `f` on the statistics kept by the writer or the follower, whichever
+    /// there is, holding it so that nothing else updates the keydirs.
+    fn with_stats<T>(&self, f: impl FnOnce(&mut KeyspaceStatistics) -> T) -> T {
+        match self {
+            Role::Writer(writer) => f(writer.lock().get_stats_mut()),
+            Role::Follower(follower) => f(follower.lock().get_stats_mut()),
+        }
+    }
 }
 
 impl Handle {
     fn put(&self, key: Bytes, value: Bytes) -> Result<(), Error> {
+        self.ctx.metrics.time(Op::Put, || {
+            let seq = {
+                let mut writer = self.lock_writer()?;
+                writer.put(&self.keydir, key, value)?;
+                writer.seq()
+            };
+            self.commit(seq)
+        })
+    }
+
+    fn del(&self, key: Bytes) -> Result<bool, Error> {
+        self.ctx.metrics.time(Op::Del, || {
+            let (deleted, seq) = {
+                let mut writer = self.lock_writer()?;
+                (writer.delete(&self.keydir, key)?, writer.seq())
+            };
+            self.commit(seq)?;
+            Ok(deleted)
+        })
+    }
+
+    /// Sets `key` to `value` for `ttl`, after which the key reads as deleted.
+    pub fn set_with_ttl(&self, key: Bytes, value: Bytes, ttl: Duration) -> Result<(), Error> {
+        self.ctx.metrics.time(Op::Put, || {
+            let seq = {
+                let mut writer = self.lock_writer()?;
+                writer.put_with_ttl(&self.keydir, key, value, ttl)?;
+                writer.seq()
+            };
+            self.commit(seq)
+        })
+    }
+
+    /// Atomically replaces the value of `key` with `new` if it currently is
+    /// `expected`, where `None` stands for a missing key on either side.
+    /// Returns whether the swap happened.
+    pub fn compare_and_swap(
+        &self,
+        key: Bytes,
+        expected: Option<Bytes>,
+        new: Option<Bytes>,
+    ) -> Result<bool, Error> {
+        self.ctx.metrics.time(Op::Cas, || {
+            let (swapped, seq) = {
+                let mut writer = self.lock_writer()?;
+                let swapped = writer.compare_and_swap(&self.keydir, key, expected, new)?;
+                (swapped, writer.seq())
+            };
+            if swapped {
+                self.commit(seq)?;
+            }
+            Ok(swapped)
+        })
+    }
+
+    /// Sets `key` to `value` unless the key exists. Returns whether it was
+    /// set.
+    pub fn put_if_absent(&self, key: Bytes, value: Bytes) -> Result<bool, Error> {
+        self.compare_and_swap(key, None, Some(value))
+    }
+
+    /// Deletes `key` if its value is `expected`. Returns whether it was
+    /// deleted.
+    pub fn delete_if_equals(&self, key: Bytes, expected: Bytes) -> Result<bool, Error> {
+        self.compare_and_swap(key, Some(expected), None)
+    }
+
+    /// Writes every operation in `batch`, such that after a crash either all
+    /// of them are recovered or none, and readers never see only some of them.
+    pub fn write_batch(&self, batch: WriteBatch) -> Result<(), Error> {
+        self.ctx.metrics.time(Op::Batch, || {
+            let seq = {
+                let mut writer = self.lock_writer()?;
+                writer.write_batch(&self.keydir, batch)?;
+                writer.seq()
+            };
+            self.commit(seq)
+        })
+    }
+
+    /// Waits for the append numbered `seq` to reach disk if the sync policy
+    /// requires it before acknowledging a write.
+    fn commit(&self, seq: u64) -> Result<(), Error> {
+        match self.sync_policy {
+            SyncPolicy::Always => self.group_commit.wait(seq, self.writer()?),
+            _ => Ok(()),
+        }
+    }
+
+    fn get(&self, key: Bytes) -> Result<Option<Bytes>, Error> {
+        self.ctx.metrics.time(Op::Get, || {
+            if self.ctx.is_closed() {
+                return Err(Error::Closed);
+            }
+            let start = Instant::now();
+            let backoff = Backoff::new();
+            let permit = loop {
+                if let Ok(permit) = self.reader_permits.try_acquire() {
+                    break permit;
+                }
+                backoff.spin();
+            };
+            self.ctx.metrics.reader_wait(start.elapsed());
+            self.read(permit, key)
+        })
+    }
+
+    /// Looks `key` up with a pooled reader, which `_permit` sets aside for
+    /// this call.
+    fn read(&self, _permit: SemaphorePermit<'_>, key: Bytes) -> Result<Option<Bytes>, Error> {
+        let reader = self.readers.pop().expect("a reader for every permit");
+        let result = reader.get(&self.keydir, key);
+        self.readers.push(reader).expect("unreachable error");
+        result
+    }
+
+    /// Iterates over the entries whose keys fall in `range`, in key order.
+    pub fn range<R: RangeBounds<Bytes>>(&self, range: R) -> Range {
+        self.scan(range, false)
+    }
+
+    /// Iterates over the entries whose keys fall in `range`, in reverse key
+    /// order.
+    pub fn range_rev<R: RangeBounds<Bytes>>(&self, range: R) -> Range {
+        self.scan(range, true)
+    }
+
+    /// Iterates over the entries whose keys start with `prefix`, in key
+    /// order.
+    pub fn scan_prefix(&self, prefix: Bytes) -> Range {
+        self.scan(scan::prefix_bounds(prefix), false)
+    }
+
+    /// Lists the keys that start with `prefix`, in key order, without
+    /// reading any values.
+    pub fn keys(&self, prefix: Bytes) -> Keys {
+        let (start, end) = scan::prefix_bounds(prefix);
+        Keys::new(self.clone(), Cursor::new(start, end, false))
+    }
+
+    /// Reports per-file and overall statistics of the store. Key counts, live
+    /// and dead, are those of the keyspace of this handle, while sizes cover
+    /// every keyspace.
+    pub fn stats(&self) -> Result<Stats, Error> {
         if self.ctx.is_closed() {
             return Err(Error::Closed);
         }
-        self.writer.lock().put(key, value)
+        // Values count as dead as soon as they expire, not only once the
+        // compaction task, which may be disabled, swept them.
+        self.expire()?;
+        // Copied out so that the writer is not held while files are listed.
+        let (file_stats, active) = match &self.role {
+            Role::Writer(writer) => {
+                let writer = writer.lock();
+                let active = (writer.active_fileid(), writer.written_bytes());
+                (writer.get_stats().keyspace(self.keydir.id()), Some(active))
+            }
+            Role::Follower(follower) => {
+                let file_stats = follower.lock().get_stats().keyspace(self.keydir.id());
+                (file_stats, None)
+            }
+        };
+        stats::collect(&self.ctx, self.keydir.len(), &file_stats, active)
     }
 
-    fn del(&self, key: Bytes) -> Result<bool, Error> {
+    /// Takes a consistent read-only view of the store as it is now. Writes
+    /// wait while the keydir is copied.
+    pub fn snapshot(&self) -> Result<Snapshot, Error> {
         if self.ctx.is_closed() {
             return Err(Error::Closed);
         }
-        self.writer.lock().delete(key)
+        // Holding the writer or follower keeps the keydir still.
+        self.role
+            .with_stats(|_| Snapshot::new(self.ctx.clone(), &self.keydir))
     }
 
-    fn get(&self, key: Bytes) -> Result<Option<Bytes>, Error> {
+    fn scan<R: RangeBounds<Bytes>>(&self, range: R, rev: bool) -> Range {
+        let (start, end) = (range.start_bound().cloned(), range.end_bound().cloned());
+        Range::new(self.clone(), Cursor::new(start, end, rev))
+    }
+
+    /// Rewrites the live entries of every sealed data file into new data
+    /// files and removes the old ones, reclaiming the space taken by
+    /// overwritten and deleted keys. Reads and writes proceed while it runs.
+    pub fn merge(&self) -> Result<(), Error> {
+        let writer = self.writer()?;
+        self.merger.lock().merge(writer)
+    }
+
+    /// Drops expired keys from the keydir, so that their values count as dead
+    /// in the stats and when picking files to compact.
+    fn expire(&self) -> Result<(), Error> {
+        if self.ctx.is_closed() {
+            return Err(Error::Closed);
+        }
+        let expired = self.keydir.expired(utils::timestamp());
+        if expired.is_empty() {
+            return Ok(());
+        }
+        self.role.with_stats(|stats| {
+            for (key, fileid, pos) in expired {
+                self.keydir.expire(&key, fileid, pos, stats);
+            }
+        });
+        Ok(())
+    }
+
+    fn compact(&self, min_fragmentation: f64, min_dead_bytes: u64) -> Result<(), Error> {
+        let writer = self.writer()?;
+        self.merger
+            .lock()
+            .merge_fragmented(writer, min_fragmentation, min_dead_bytes)
+    }
+
+    /// Writes a consistent copy of the store to `dest` while it keeps serving
+    /// reads and writes. Sealed data files are hard-linked where possible, so
+    /// this is cheap on the same file system. The copy can be opened like any
+    /// other store.
+    pub fn checkpoint<P: AsRef<Path>>(&self, dest: P) -> Result<(), Error> {
+        let writer = self.writer()?;
+        backup::checkpoint(&self.ctx, writer, &self.merger, dest, None)
+    }
+
+    /// Like [`checkpoint`](Self::checkpoint), but only copies the data files
+    /// that are newer than those in the checkpoint at `base`, which can be
+    /// full or incremental itself. [`Bitcask::restore`] puts the chain back
+    /// together.
+    pub fn checkpoint_incremental<P, Q>(&self, dest: P, base: Q) -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let writer = self.writer()?;
+        backup::checkpoint(&self.ctx, writer, &self.merger, dest, Some(base.as_ref()))
+    }
+
+    /// Flushes everything written so far to disk.
+    pub fn sync(&self) -> Result<(), Error> {
+        self.ctx
+            .metrics
+            .time(Op::Sync, || self.lock_writer()?.sync())
+    }
+
+    /// Subscribes to the writes to keys that start with `prefix`, an empty
+    /// prefix matching every key. Only writes made after this call are seen.
+    pub fn subscribe(&self, prefix: Bytes) -> Result<Subscription, Error> {
+        self.writer()?;
+        let events = self.ctx.subscribe().ok_or(Error::Closed)?;
+        Ok(Subscription::new(self.keydir.id(), prefix, events))
+    }
+
+    /// Reads the writes made after `cursor` straight from the data files, so
+    /// that a reader can pick up where it stopped, even in another process.
+    /// [`ChangeCursor::default`] starts from the oldest write, as long as no
+    /// merge has run yet.
+    pub fn changes(&self, cursor: ChangeCursor) -> Result<Changes, Error> {
         if self.ctx.is_closed() {
             return Err(Error::Closed);
         }
-        let backoff = Backoff::new();
-        loop {
-            if let Some(reader) = self.readers.pop() {
-                let result = reader.get(key);
-                self.readers.push(reader).expect("unreachable error");
-                break result;
+        let wakeups = match self.role {
+            Role::Writer(_) => Some(self.ctx.subscribe().ok_or(Error::Closed)?),
+            Role::Follower(_) => None,
+        };
+        Ok(Changes::new(
+            self.ctx.clone(),
+            self.keydir.id(),
+            cursor,
+            wakeups,
+        ))
+    }
+
+    /// Returns the cursor right after the last write, from which
+    /// [`changes`](Self::changes) only yields writes made later.
+    pub fn change_cursor(&self) -> Result<ChangeCursor, Error> {
+        match &self.role {
+            Role::Writer(_) => {
+                let writer = self.writer()?.lock();
+                Ok(ChangeCursor {
+                    fileid: writer.active_fileid(),
+                    pos: writer.written_bytes(),
+                })
+            }
+            Role::Follower(follower) => {
+                let (fileid, pos) = follower.lock().newest().unwrap_or_default();
+                Ok(ChangeCursor { fileid, pos })
             }
-            backoff.spin();
         }
     }
 
-    #[allow(dead_code)]
-    fn merge(&self) -> Result<(), Error> {
-        unimplemented!();
+    /// Counters and latency histograms of the operations served since the
+    /// store was opened, which can be rendered for Prometheus.
+    pub fn metrics(&self) -> &Metrics {
+        &self.ctx.metrics
     }
 
-    #[allow(dead_code)]
-    fn sync(&self) -> Result<(), Error> {
+    /// Brings a read-only handle up to date with what the writing process
+    /// appended since the store was opened or last refreshed. Does nothing
+    /// for a handle that owns the writer.
+    pub fn refresh(&self) -> Result<(), Error> {
         if self.ctx.is_closed() {
             return Err(Error::Closed);
         }
-        self.writer.lock().sync()
+        match &self.role {
+            Role::Writer(_) => Ok(()),
+            Role::Follower(follower) => follower.lock().refresh(),
+        }
+    }
+
+    fn writer(&self) -> Result<&Mutex<Writer>, Error> {
+        if self.ctx.is_closed() {
+            return Err(Error::Closed);
+        }
+        match &self.role {
+            Role::Writer(writer) => Ok(writer),
+            Role::Follower(_) => Err(Error::ReadOnly),
+        }
+    }
+
+    /// Locks the writer for a write or sync, recording how long that took.
+    fn lock_writer(&self) -> Result<MutexGuard<'_, Writer>, Error> {
+        let writer = self.writer()?;
+        let start = Instant::now();
+        let writer = writer.lock();
+        self.ctx.metrics.writer_wait(start.elapsed());
+        Ok(writer)
     }
 
     fn close(&self) {
@@ -159,123 +672,278 @@ impl KeyValueStorage for Handle {
     }
 }
 
-fn rebuild_storage<P: AsRef<Path>>(
+struct Storage {
+    keydirs: Keydirs,
+    stats: KeyspaceStatistics,
+    keyspaces: Registry,
+    /// Ids of the replayed data files, oldest first.
+    fileids: Vec<u64>,
+    /// Number of bytes replayed from the newest data file.
+    newest_len: u64,
+}
+
+impl Storage {
+    fn active_fileid(&self) -> u64 {
+        self.fileids.last().map(|id| id + 1).unwrap_or_default()
+    }
+}
+
+/// What recovery does with an incomplete or corrupt tail of the newest data
+/// file, which a crash can leave behind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TornTail {
+    /// Cut the file back to its last complete record.
+    Truncate,
+    /// Stop replaying at the last complete record but leave the file as is.
+    Ignore,
+}
+
+fn rebuild_storage<P: AsRef<Path>>(path: P, torn_tail: TornTail) -> Result<Storage, Error> {
+    let keyspaces = Registry::load(&path)?;
+    let keydirs = Keydirs::default();
+    for id in keyspaces.live() {
+        keydirs.get(id);
+    }
+    // Records of dropped keyspaces are replayed as dead.
+    for id in keyspaces.dropped() {
+        keydirs.drop_keyspace(id);
+    }
+    let mut stats = KeyspaceStatistics::default();
+    let fileids: Vec<u64> = utils::sorted_fileids(&path)?.collect();
+
+    let mut newest_len = 0;
+    for (i, &fileid) in fileids.iter().enumerate() {
+        // Only the newest data file can have been cut short by a crash; every
+        // older one was sealed by a rotation.
+        let torn_tail = (i + 1 == fileids.len()).then_some(torn_tail);
+        newest_len = populate_keydir(&path, fileid, &keydirs, &mut stats, torn_tail)?;
+    }
+
+    Ok(Storage {
+        keydirs,
+        stats,
+        keyspaces,
+        fileids,
+        newest_len,
+    })
+}
+
+/// Replays a data file into the keydir, preferring its hint file, and returns
+/// the number of bytes of the data file that were replayed.
+fn populate_keydir<P>(
     path: P,
-) -> Result<
-    (
-        SkipMap<Bytes, KeyDirEntry>,
-        HashMap<u64, LogStatistics>,
-        u64,
-    ),
-    Error,
-> {
-    let keydir = SkipMap::default();
-    let mut stats = HashMap::default();
-    let fileids = utils::sorted_fileids(&path)?;
-
-    let mut active_fileid = None;
-    for fileid in fileids {
-        match &mut active_fileid {
-            None => active_fileid = Some(fileid),
-            Some(id) => {
-                if fileid > *id {
-                    *id = fileid;
-                }
-            }
+    fileid: u64,
+    keydirs: &Keydirs,
+    stats: &mut KeyspaceStatistics,
+    torn_tail: Option<TornTail>,
+) -> Result<u64, Error>
+where
+    P: AsRef<Path>,
+{
+    match populate_keydir_with_hintfile(&path, fileid, keydirs, stats) {
+        Ok(()) => Ok(fs::metadata(utils::datafile_name(&path, fileid))?.len()),
+        Err(Error::Io(ref ioe)) if ioe.kind() == io::ErrorKind::NotFound => {
+            populate_keydir_with_datafile(&path, fileid, 0, keydirs, stats, torn_tail)
         }
-        if let Err(e) = populate_keydir_with_hintfile(&path, fileid, &keydir, &mut stats) {
-            match e {
-                Error::Io(ref ioe) => match ioe.kind() {
-                    io::ErrorKind::NotFound => {
-                        populate_keydir_with_datafile(&path, fileid, &keydir, &mut stats)?;
-                    }
-                    _ => return Err(e),
-                },
-                _ => return Err(e),
-            }
+        // Hint files only speed up opening, so the data file is the one to
+        // trust when they are damaged.
+        Err(e @ (Error::Corruption { .. } | Error::Serialization(_))) => {
+            eprintln!("replaying data file {fileid} instead of its hint file - {e}");
+            populate_keydir_with_datafile(&path, fileid, 0, keydirs, stats, torn_tail)
         }
+        Err(e) => Err(e),
     }
-
-    let active_fileid = active_fileid.map(|id| id + 1).unwrap_or_default();
-    Ok((keydir, stats, active_fileid))
 }
 
+/// Replays a hint file, which is read to its end before any of it is
+/// applied, so that a damaged one leaves the keydirs and `stats` untouched.
 fn populate_keydir_with_hintfile<P>(
     path: P,
     fileid: u64,
-    keydir: &SkipMap<Bytes, KeyDirEntry>,
-    stats: &mut HashMap<u64, LogStatistics>,
+    keydirs: &Keydirs,
+    stats: &mut KeyspaceStatistics,
 ) -> Result<(), Error>
 where
     P: AsRef<Path>,
 {
     let file = log::open(utils::hintfile_name(&path, fileid))?;
-    let mut hintfile_iter = LogIterator::new(file)?;
+    let mut hintfile_iter = LogIterator::new(file, fileid)?;
+    let mut entries = Vec::new();
     while let Some((_, entry)) = hintfile_iter.next::<HintFileEntry>()? {
+        entries.push(entry);
+    }
+    let now = utils::timestamp();
+    for entry in entries {
+        let keydir = keydirs.get(entry.keyspace);
+        // An expired value shadows older ones just like a tombstone does, and
+        // nothing of a dropped keyspace is live.
+        if entry.tombstone || utils::is_expired(entry.expires, now) || keydir.is_dropped() {
+            stats.file(entry.keyspace, fileid).add_dead(entry.len);
+            if let Some(prev_entry) = keydir.remove(&entry.key) {
+                stats
+                    .file(entry.keyspace, prev_entry.value().fileid)
+                    .overwrite(prev_entry.value().len);
+            }
+            continue;
+        }
         let keydir_entry = KeyDirEntry {
             fileid,
             len: entry.len,
             pos: entry.pos,
             tstamp: entry.tstamp,
+            expires: entry.expires,
         };
-        stats.entry(fileid).or_default().add_live();
-        let prev_entry = keydir.get(&entry.key);
-        keydir.insert(entry.key, keydir_entry);
+        stats.file(entry.keyspace, fileid).add_live(entry.len);
+        let prev_entry = keydir.insert(entry.key, keydir_entry);
         if let Some(prev_entry) = prev_entry {
             stats
-                .entry(prev_entry.value().fileid)
-                .or_default()
+                .file(entry.keyspace, prev_entry.value().fileid)
                 .overwrite(prev_entry.value().len);
         }
     }
     Ok(())
 }
 
+/// Replays a data file into the keydir from offset `start` and returns the
+/// offset past the last complete record or batch. Unless `torn_tail` says how
+/// to deal with it, an incomplete or corrupt record fails the replay; even then
+/// only one that runs to the end of the file is dropped. A batch missing its
+/// commit marker is never applied.
 fn populate_keydir_with_datafile<P>(
     path: P,
     fileid: u64,
-    keydir: &SkipMap<Bytes, KeyDirEntry>,
-    stats: &mut HashMap<u64, LogStatistics>,
-) -> Result<(), Error>
+    start: u64,
+    keydirs: &Keydirs,
+    stats: &mut KeyspaceStatistics,
+    torn_tail: Option<TornTail>,
+) -> Result<u64, Error>
 where
     P: AsRef<Path>,
 {
-    let file = log::open(utils::datafile_name(&path, fileid))?;
-    let mut datafile_iter = LogIterator::new(file)?;
-    while let Some((datafile_index, datafile_entry)) = datafile_iter.next::<DataFileEntry>()? {
-        match datafile_entry.value {
-            None => {
-                stats
-                    .entry(fileid)
-                    .or_default()
-                    .add_dead(datafile_index.len);
-                if let Some(prev_entry) = keydir.remove(&datafile_entry.key) {
-                    stats
-                        .entry(prev_entry.value().fileid)
-                        .or_default()
-                        .overwrite(prev_entry.value().len);
-                }
+    let datafile = utils::datafile_name(&path, fileid);
+    let file = log::open(&datafile)?;
+    let file_len = file.metadata()?.len();
+    let mut datafile_iter = LogIterator::new_at(file, fileid, start)?;
+    let mut valid_len = datafile_iter.pos();
+    // The number of records still to come of the open batch, and those read.
+    let mut batch: Option<(u64, Vec<_>)> = None;
+    let now = utils::timestamp();
+    loop {
+        let (datafile_index, datafile_entry) = match datafile_iter.next::<DataFileEntry>() {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            // A crash only tears the end of a file. A bad record followed by
+            // more data is something else, and is left for a human to look
+            // at along with the valid records after it.
+            Err(Error::Corruption { .. } | Error::Serialization(_))
+                if torn_tail.is_some() && datafile_iter.failed_at_tail() =>
+            {
+                break
             }
-            Some(_) => {
-                let keydir_entry = KeyDirEntry {
+            Err(e) => return Err(e),
+        };
+        let end = datafile_index.pos + datafile_index.len;
+        // Batches are appended in one piece, so a record past the end of an
+        // open one means that a failed write left it there. It is dropped.
+        if datafile_entry.batch.is_none() && batch.as_ref().is_some_and(|(left, _)| *left == 0) {
+            abandon_batch(fileid, batch.take(), stats);
+        }
+        match (datafile_entry.batch, &mut batch) {
+            (Some(BatchMarker::Begin), _) => {
+                let len = datafile_entry.batch_len().ok_or(Error::Corruption {
                     fileid,
-                    len: datafile_index.len,
                     pos: datafile_index.pos,
-                    tstamp: datafile_entry.tstamp,
-                };
-                stats.entry(fileid).or_default().add_live();
-                let prev_entry = keydir.get(&datafile_entry.key);
-                keydir.insert(datafile_entry.key, keydir_entry);
-                if let Some(prev_entry) = prev_entry {
-                    stats
-                        .entry(prev_entry.value().fileid)
-                        .or_default()
-                        .overwrite(prev_entry.value().len);
-                }
+                })?;
+                let open = batch.replace((len, vec![(datafile_index, datafile_entry)]));
+                abandon_batch(fileid, open, stats);
+            }
+            (Some(BatchMarker::Commit), Some(_)) => {
+                // Every record of a batch belongs to the keyspace of its
+                // markers. A follower replays into the keydir readers look
+                // keys up in.
+                let keydir = keydirs.get(datafile_entry.keyspace);
+                keydir.apply_batch(|| {
+                    for (index, entry) in batch.take().into_iter().flat_map(|(_, entries)| entries)
+                    {
+                        replay_datafile_entry(fileid, index, entry, now, &keydir, stats);
+                    }
+                });
+                replay_datafile_entry(fileid, datafile_index, datafile_entry, now, &keydir, stats);
+                valid_len = end;
+            }
+            (Some(BatchMarker::Commit), None) => {}
+            (Some(BatchMarker::Merge), _) => valid_len = end,
+            (None, Some((left, entries))) => {
+                *left -= 1;
+                entries.push((datafile_index, datafile_entry));
+            }
+            (None, None) => {
+                let keydir = keydirs.get(datafile_entry.keyspace);
+                replay_datafile_entry(fileid, datafile_index, datafile_entry, now, &keydir, stats);
+                valid_len = end;
+            }
+        }
+    }
+
+    if torn_tail == Some(TornTail::Truncate) && valid_len < file_len {
+        eprintln!(
+            "dropping {} bytes of incomplete records from the end of data file {fileid}",
+            file_len - valid_len
+        );
+        log::truncate(&datafile, valid_len)?;
+    }
+    Ok(valid_len)
+}
+
+/// Counts the records of a batch that was never committed as dead.
+fn abandon_batch(
+    fileid: u64,
+    batch: Option<(u64, Vec<(LogIndex, DataFileEntry)>)>,
+    stats: &mut KeyspaceStatistics,
+) {
+    for (index, entry) in batch.into_iter().flat_map(|(_, entries)| entries) {
+        stats.file(entry.keyspace, fileid).add_dead(index.len);
+    }
+}
+
+fn replay_datafile_entry(
+    fileid: u64,
+    index: LogIndex,
+    entry: DataFileEntry,
+    now: i64,
+    keydir: &Keydir,
+    stats: &mut KeyspaceStatistics,
+) {
+    let keyspace = entry.keyspace;
+    match entry.value {
+        // Batch markers take up space but never hold a key.
+        None if entry.batch.is_some() => stats.file(keyspace, fileid).add_dead(index.len),
+        Some(_) if !utils::is_expired(entry.expires, now) && !keydir.is_dropped() => {
+            let keydir_entry = KeyDirEntry {
+                fileid,
+                len: index.len,
+                pos: index.pos,
+                tstamp: entry.tstamp,
+                expires: entry.expires,
+            };
+            stats.file(keyspace, fileid).add_live(index.len);
+            if let Some(prev_entry) = keydir.insert(entry.key, keydir_entry) {
+                stats
+                    .file(keyspace, prev_entry.value().fileid)
+                    .overwrite(prev_entry.value().len);
+            }
+        }
+        // A tombstone, or an expired value, which shadows older ones the same
+        // way, or a record of a dropped keyspace.
+        _ => {
+            stats.file(keyspace, fileid).add_dead(index.len);
+            if let Some(prev_entry) = keydir.remove(&entry.key) {
+                stats
+                    .file(keyspace, prev_entry.value().fileid)
+                    .overwrite(prev_entry.value().len);
             }
         }
     }
-    Ok(())
 }
 
 #[derive(Error, Debug)]
@@ -286,19 +954,107 @@ pub enum Error {
     Io(#[from] io::Error),
     #[error("Serialization error - {0}")]
     Serialization(#[from] bincode::Error),
+    #[error("corrupted record in file {fileid} at offset {pos}")]
+    Corruption { fileid: u64, pos: u64 },
+    /// A file of the store is in a format this build does not read. Version
+    /// 0 stands for files written before the format was versioned, which
+    /// [`Bitcask::upgrade`] converts.
+    #[error(
+        "file {fileid} has format version {version}, expected {}",
+        log::VERSION
+    )]
+    UnsupportedVersion { fileid: u64, version: u16 },
+    #[error("read-only!")]
+    ReadOnly,
+    #[error("already exists!")]
+    AlreadyExists,
+    #[error(
+        "locked by process {}",
+        .pid.map_or_else(|| "unknown".to_string(), |pid| pid.to_string())
+    )]
+    Locked { pid: Option<u32> },
+    #[error("subscription lagged behind by {0} writes")]
+    Lagged(u64),
+    #[error("cursor points at writes a merge removed")]
+    CursorTooOld,
+    #[error("keyspace dropped!")]
+    KeyspaceDropped,
 }
 
+/// Locates one entry of the data file with the same id. Hint files list
+/// tombstones too, so that a key deleted here stays deleted when older files
+/// are replayed before it; a tombstone is only dropped by a merge that also
+/// rewrites every older data file.
 #[derive(Serialize, Deserialize, Debug)]
 struct HintFileEntry {
     tstamp: i64,
+    keyspace: u32,
     len: u64,
     pos: u64,
     key: Bytes,
+    tombstone: bool,
+    expires: Option<i64>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct DataFileEntry {
     tstamp: i64,
+    /// Id of the keyspace the key belongs to, 0 for the default one. Batch
+    /// markers carry that of the batch.
+    keyspace: u32,
     key: Bytes,
     value: Option<Bytes>,
+    batch: Option<BatchMarker>,
+    /// Timestamp from which the value reads as deleted.
+    expires: Option<i64>,
+}
+
+impl DataFileEntry {
+    fn new(keyspace: u32, tstamp: i64, key: Bytes, value: Option<Bytes>) -> Self {
+        Self {
+            tstamp,
+            keyspace,
+            key,
+            value,
+            batch: None,
+            expires: None,
+        }
+    }
+
+    fn marker(keyspace: u32, tstamp: i64, marker: BatchMarker) -> Self {
+        Self {
+            tstamp,
+            keyspace,
+            key: Bytes::new(),
+            value: None,
+            batch: Some(marker),
+            expires: None,
+        }
+    }
+
+    /// The marker heading a batch of `len` records.
+    fn begin(keyspace: u32, tstamp: i64, len: u64) -> Self {
+        Self {
+            key: Bytes::copy_from_slice(&len.to_be_bytes()),
+            ..Self::marker(keyspace, tstamp, BatchMarker::Begin)
+        }
+    }
+
+    /// Number of records in the batch this `Begin` marker heads.
+    fn batch_len(&self) -> Option<u64> {
+        self.key[..].try_into().ok().map(u64::from_be_bytes)
+    }
+}
+
+/// Brackets the records of a write batch in a data file. The records between
+/// a `Begin` and a `Commit` only take effect together, once the `Commit` is
+/// on disk.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+enum BatchMarker {
+    /// Its key holds the number of records in the batch.
+    Begin,
+    Commit,
+    /// Heads a data file written by a merge, whose records are copies of
+    /// older ones rather than new writes. Its key lists the merged files.
+    Merge,
 }
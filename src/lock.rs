@@ -0,0 +1,45 @@
+use std::{
+    fs::{self, TryLockError},
+    io::{Read, Write},
+    path::Path,
+    process,
+};
+
+use crate::{utils, Error};
+
+/// An advisory lock on the lock file in a store's directory, held for as
+/// long as the store has a writer. The lock file records the holder's PID.
+#[derive(Debug)]
+pub(super) struct DirLock(fs::File);
+
+impl DirLock {
+    pub(super) fn acquire<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(utils::lockfile_name(path))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                file.read_to_string(&mut pid)?;
+                return Err(Error::Locked {
+                    pid: pid.trim().parse().ok(),
+                });
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        file.set_len(0)?;
+        file.write_all(process::id().to_string().as_bytes())?;
+        file.sync_all()?;
+        Ok(Self(file))
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
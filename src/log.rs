@@ -1,9 +1,9 @@
 use std::{
+    collections::HashMap,
     fs,
-    io::{self, Write},
+    io::{self, Read, Seek, SeekFrom, Write},
     num::NonZeroUsize,
     path::Path,
-    u64, usize,
 };
 
 use bytes::Buf;
@@ -15,23 +15,111 @@ use crate::{
     utils, Error,
 };
 
+/// Every file starts with `magic | version: u16 | kind: u16`, little endian,
+/// followed by its records.
+const MAGIC: [u8; 4] = *b"BCSK";
+
+/// Version of the file header and record format written by this build. Files
+/// without a header come from before versioning and count as version 0, which
+/// [`Bitcask::upgrade`](crate::Bitcask::upgrade) converts.
+pub(super) const VERSION: u16 = 1;
+
+pub(super) const FILE_HEADER_LEN: u64 = 8;
+
+/// What a file holds, as recorded in its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum FileKind {
+    /// Records appended one after the other: data files written by the
+    /// writer, hint files and the other files of a store.
+    Log = 0,
+    /// A data file written by a merge, holding copies of older records.
+    Merged = 1,
+}
+
+impl FileKind {
+    fn from_u16(kind: u16) -> Option<Self> {
+        match kind {
+            0 => Some(Self::Log),
+            1 => Some(Self::Merged),
+            _ => None,
+        }
+    }
+}
+
+fn file_header(kind: FileKind) -> [u8; FILE_HEADER_LEN as usize] {
+    let mut header = [0; FILE_HEADER_LEN as usize];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    header[6..].copy_from_slice(&(kind as u16).to_le_bytes());
+    header
+}
+
+/// Checks the header at the start of a file, given as many of its first
+/// bytes as there are up to the header length. Returns `None` for an empty
+/// file.
+fn parse_file_header(header: &[u8], fileid: u64) -> Result<Option<FileKind>, Error> {
+    if header.is_empty() {
+        return Ok(None);
+    }
+    if !MAGIC.starts_with(&header[..header.len().min(4)]) {
+        return Err(Error::UnsupportedVersion { fileid, version: 0 });
+    }
+    // Cut short while the file was created.
+    let Some(header) = header.get(..FILE_HEADER_LEN as usize) else {
+        return Err(Error::Corruption { fileid, pos: 0 });
+    };
+    let version = u16::from_le_bytes(header[4..6].try_into().unwrap());
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion { fileid, version });
+    }
+    let kind = u16::from_le_bytes(header[6..].try_into().unwrap());
+    FileKind::from_u16(kind)
+        .map(Some)
+        .ok_or(Error::Corruption { fileid, pos: 0 })
+}
+
+/// Every record is framed as `crc: u32 | len: u64 | payload`, little endian,
+/// where the CRC32C covers both the length and the bincode encoded payload.
+const HEADER_LEN: usize = 12;
+
+fn encode<T: Serialize>(entry: &T) -> Result<Vec<u8>, Error> {
+    let payload_len = bincode::serialized_size(entry)?;
+    let mut record = Vec::with_capacity(HEADER_LEN + payload_len as usize);
+    record.extend_from_slice(&[0; 4]);
+    record.extend_from_slice(&payload_len.to_le_bytes());
+    bincode::serialize_into(&mut record, entry)?;
+    let crc = crc32c::crc32c(&record[4..]);
+    record[..4].copy_from_slice(&crc.to_le_bytes());
+    Ok(record)
+}
+
+/// Checks the CRC of a framed record and returns its payload.
+fn verify(record: &[u8]) -> Option<&[u8]> {
+    let (header, payload) = record.split_at_checked(HEADER_LEN)?;
+    let crc = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let len = u64::from_le_bytes(header[4..].try_into().unwrap());
+    let actual = crc32c::crc32c_append(crc32c::crc32c(&header[4..]), payload);
+    (crc == actual && len == payload.len() as u64).then_some(payload)
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub(super) struct LogIndex {
     pub(super) len: u64,
     pub(super) pos: u64,
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub(super) struct LogStatistics {
     live_keys: u64,
+    live_bytes: u64,
     dead_keys: u64,
     dead_bytes: u64,
 }
 
-#[allow(dead_code)]
 impl LogStatistics {
-    pub(super) fn add_live(&mut self) {
+    pub(super) fn add_live(&mut self, nbytes: u64) {
         self.live_keys += 1;
+        self.live_bytes += nbytes;
     }
 
     pub(super) fn add_dead(&mut self, nbytes: u64) {
@@ -41,10 +129,26 @@ impl LogStatistics {
 
     pub(super) fn overwrite(&mut self, nbytes: u64) {
         self.live_keys -= 1;
+        self.live_bytes -= nbytes;
         self.dead_keys += 1;
         self.dead_bytes += nbytes;
     }
 
+    /// Counts every live entry as dead.
+    fn kill(&mut self) {
+        self.dead_keys += self.live_keys;
+        self.dead_bytes += self.live_bytes;
+        self.live_keys = 0;
+        self.live_bytes = 0;
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.live_keys += other.live_keys;
+        self.live_bytes += other.live_bytes;
+        self.dead_keys += other.dead_keys;
+        self.dead_bytes += other.dead_bytes;
+    }
+
     pub(super) fn live_keys(&self) -> u64 {
         self.live_keys
     }
@@ -69,6 +173,56 @@ impl LogStatistics {
     }
 }
 
+/// The [`LogStatistics`] of every data file, kept apart for each keyspace.
+#[derive(Clone, Debug, Default)]
+pub(super) struct KeyspaceStatistics(HashMap<u32, HashMap<u64, LogStatistics>>);
+
+impl KeyspaceStatistics {
+    /// Statistics of the records of keyspace `keyspace` in data file `fileid`.
+    pub(super) fn file(&mut self, keyspace: u32, fileid: u64) -> &mut LogStatistics {
+        self.0
+            .entry(keyspace)
+            .or_default()
+            .entry(fileid)
+            .or_default()
+    }
+
+    /// Statistics of the records of keyspace `keyspace`, by data file.
+    pub(super) fn keyspace(&self, keyspace: u32) -> HashMap<u64, LogStatistics> {
+        self.0.get(&keyspace).cloned().unwrap_or_default()
+    }
+
+    /// Statistics of all records in data file `fileid`, `None` if none were
+    /// counted.
+    pub(super) fn total(&self, fileid: u64) -> Option<LogStatistics> {
+        let mut files = self.0.values().filter_map(|files| files.get(&fileid));
+        let mut total = files.next()?.clone();
+        files.for_each(|stats| total.add(stats));
+        Some(total)
+    }
+
+    /// Forgets data file `fileid`, which a merge removed.
+    pub(super) fn remove(&mut self, fileid: u64) {
+        self.0.retain(|_, files| {
+            files.remove(&fileid);
+            !files.is_empty()
+        });
+    }
+
+    /// Counts every live entry of keyspace `keyspace`, which was dropped, as
+    /// dead.
+    pub(super) fn drop_keyspace(&mut self, keyspace: u32) {
+        for stats in self
+            .0
+            .get_mut(&keyspace)
+            .into_iter()
+            .flat_map(HashMap::values_mut)
+        {
+            stats.kill();
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(super) struct LogDir(LruCache<u64, LogReader>);
 
@@ -93,7 +247,7 @@ impl LogDir {
             Some(reader) => unsafe { reader.at(len, pos) },
             None => {
                 let file = open(utils::datafile_name(path, fileid))?;
-                let mut reader = LogReader::new(file)?;
+                let mut reader = LogReader::new(file, fileid)?;
                 let result = unsafe { reader.at::<T>(len, pos) };
                 self.0.put(fileid, reader);
                 result
@@ -101,6 +255,10 @@ impl LogDir {
         }
     }
 
+    pub(super) fn clear(&mut self) {
+        self.0.clear();
+    }
+
     pub(super) unsafe fn copy<P, W>(
         &mut self,
         path: P,
@@ -108,19 +266,19 @@ impl LogDir {
         len: u64,
         pos: u64,
         writer: &mut W,
-    ) -> io::Result<u64>
+    ) -> Result<u64, Error>
     where
         P: AsRef<Path>,
         W: Write,
     {
         match self.0.get_mut(&fileid) {
-            Some(reader) => unsafe { reader.copy_raw(len, pos, writer) },
+            Some(reader) => Ok(unsafe { reader.copy_raw(len, pos, writer)? }),
             None => {
                 let file = open(utils::datafile_name(&path, fileid))?;
-                let mut reader = LogReader::new(file)?;
+                let mut reader = LogReader::new(file, fileid)?;
                 let result = unsafe { reader.copy_raw(len, pos, writer) };
                 self.0.put(fileid, reader);
-                result
+                Ok(result?)
             }
         }
     }
@@ -130,36 +288,56 @@ impl LogDir {
 pub(super) struct LogReader {
     mmap: memmap2::Mmap,
     file: fs::File,
+    fileid: u64,
 }
 
 impl LogReader {
-    pub(super) fn new(file: fs::File) -> io::Result<Self> {
+    pub(super) fn new(file: fs::File, fileid: u64) -> Result<Self, Error> {
         let mmap = unsafe { memmap2::MmapOptions::new().map(&file)? };
-        Ok(Self { mmap, file })
+        parse_file_header(&mmap[..mmap.len().min(FILE_HEADER_LEN as usize)], fileid)?;
+        Ok(Self { mmap, file, fileid })
     }
 
     pub(super) unsafe fn at<T>(&mut self, len: u64, pos: u64) -> Result<T, Error>
     where
         T: DeserializeOwned,
     {
-        if pos >= self.mmap.len() as u64 {
-            self.mmap = unsafe { memmap2::MmapOptions::new().map(&self.file)? };
-        }
+        unsafe { self.map_to(pos + len)? };
         let start = pos as usize;
         let end = start + len as usize;
-        Ok(bincode::deserialize(&self.mmap[start..end])?)
+        let payload = self
+            .mmap
+            .get(start..end)
+            .and_then(verify)
+            .ok_or(Error::Corruption {
+                fileid: self.fileid,
+                pos,
+            })?;
+        Ok(bincode::deserialize(payload)?)
     }
 
     pub(super) unsafe fn copy_raw<W>(&mut self, len: u64, pos: u64, dst: &mut W) -> io::Result<u64>
     where
         W: Write,
     {
-        if pos >= self.mmap.len() as u64 {
-            self.mmap = unsafe { memmap2::MmapOptions::new().map(&self.file)? };
-        }
+        unsafe { self.map_to(pos + len)? };
         let start = pos as usize;
         let end = start + len as usize;
-        io::copy(&mut self.mmap[start..end].reader(), dst)
+        let record = self
+            .mmap
+            .get(start..end)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        io::copy(&mut record.reader(), dst)
+    }
+
+    /// Maps the file anew if the current mapping ends before `end`, as the
+    /// file may have grown since it was mapped, even in the middle of a
+    /// record.
+    unsafe fn map_to(&mut self, end: u64) -> io::Result<()> {
+        if end > self.mmap.len() as u64 {
+            self.mmap = unsafe { memmap2::MmapOptions::new().map(&self.file)? };
+        }
+        Ok(())
     }
 }
 
@@ -167,51 +345,188 @@ impl LogReader {
 pub(super) struct LogWriter(BufWriterWithPos<fs::File>);
 
 impl LogWriter {
+    /// Starts writing records to `file`, which must be new and empty.
     pub(super) fn new(file: fs::File) -> io::Result<Self> {
-        let writer = BufWriterWithPos::new(file)?;
+        Self::with_kind(file, FileKind::Log)
+    }
+
+    pub(super) fn with_kind(file: fs::File, kind: FileKind) -> io::Result<Self> {
+        let mut writer = BufWriterWithPos::new(file)?;
+        writer.write_all(&file_header(kind))?;
+        writer.flush()?;
         Ok(Self(writer))
     }
 
+    /// Length of the file, header included.
+    pub(super) fn len(&self) -> u64 {
+        self.0.pos()
+    }
+
+    /// Whether no record was appended yet.
+    pub(super) fn is_empty(&self) -> bool {
+        self.0.pos() <= FILE_HEADER_LEN
+    }
+
     pub(super) fn append<T: Serialize>(&mut self, entry: &T) -> Result<LogIndex, Error> {
-        let pos = self.0.pos();
-        bincode::serialize_into(&mut self.0, entry)?;
+        let index = self.write(entry)?;
         self.0.flush()?;
+        Ok(index)
+    }
+
+    /// Appends all of `entries` or, failing that, cuts the file back to
+    /// where it was, so that no part of them is left behind.
+    pub(super) fn append_all<T: Serialize>(
+        &mut self,
+        entries: &[T],
+    ) -> Result<Vec<LogIndex>, Error> {
+        let start = self.0.pos();
+        let result = entries
+            .iter()
+            .map(|entry| self.write(entry))
+            .collect::<Result<_, _>>()
+            .and_then(|indexes| {
+                self.0.flush()?;
+                Ok(indexes)
+            });
+        if result.is_err() {
+            self.0.truncate(start)?;
+        }
+        result
+    }
+
+    fn write<T: Serialize>(&mut self, entry: &T) -> Result<LogIndex, Error> {
+        let pos = self.0.pos();
+        self.0.write_all(&encode(entry)?)?;
         let len = self.0.pos() - pos;
         Ok(LogIndex { len, pos })
     }
 
+    pub(super) unsafe fn append_raw<P: AsRef<Path>>(
+        &mut self,
+        logdir: &mut LogDir,
+        path: P,
+        fileid: u64,
+        len: u64,
+        pos: u64,
+    ) -> Result<LogIndex, Error> {
+        let start = self.0.pos();
+        unsafe { logdir.copy(path, fileid, len, pos, &mut self.0)? };
+        self.0.flush()?;
+        let len = self.0.pos() - start;
+        Ok(LogIndex { len, pos: start })
+    }
+
     pub(super) fn sync(&mut self) -> io::Result<()> {
         self.0.get_ref().sync_all()
     }
+
+    pub(super) fn try_clone_file(&self) -> io::Result<fs::File> {
+        self.0.get_ref().try_clone()
+    }
 }
 
-pub(super) struct LogIterator(BufReaderWithPos<fs::File>);
+/// Reads the records of a file up to the length it had when the iterator was
+/// created.
+pub(super) struct LogIterator {
+    reader: BufReaderWithPos<fs::File>,
+    fileid: u64,
+    file_len: u64,
+    kind: Option<FileKind>,
+    /// Where the record that failed the last call to `next` claims to end.
+    failed_end: Option<u64>,
+}
 
 impl LogIterator {
-    pub(super) fn new(file: fs::File) -> io::Result<Self> {
-        let reader = BufReaderWithPos::new(file)?;
-        Ok(Self(reader))
+    /// Iterates over all records of `file`.
+    pub(super) fn new(file: fs::File, fileid: u64) -> Result<Self, Error> {
+        Self::new_at(file, fileid, 0)
+    }
+
+    /// Iterates over the records of `file` from offset `start` on, which is
+    /// either the start of the file or that of a record.
+    pub(super) fn new_at(mut file: fs::File, fileid: u64, start: u64) -> Result<Self, Error> {
+        let file_len = file.metadata()?.len();
+        let mut header = Vec::with_capacity(FILE_HEADER_LEN as usize);
+        file.rewind()?;
+        (&mut file).take(FILE_HEADER_LEN).read_to_end(&mut header)?;
+        let (first, kind, failed_end) = match parse_file_header(&header, fileid) {
+            Ok(kind) => (header.len() as u64, kind, None),
+            // A file cut short while it was created holds nothing.
+            Err(Error::Corruption { .. }) => (0, None, Some(FILE_HEADER_LEN)),
+            Err(e) => return Err(e),
+        };
+        file.seek(SeekFrom::Start(start.max(first)))?;
+        Ok(Self {
+            reader: BufReaderWithPos::new(file)?,
+            fileid,
+            file_len,
+            kind,
+            failed_end,
+        })
+    }
+
+    /// What the file holds, `None` if it is empty.
+    pub(super) fn kind(&self) -> Option<FileKind> {
+        self.kind
+    }
+
+    /// Offset of the next record.
+    pub(super) fn pos(&self) -> u64 {
+        self.reader.pos()
+    }
+
+    /// Whether the record that failed the last call to `next` reaches the end
+    /// of the file by its length, like one that a crash cut short does.
+    pub(super) fn failed_at_tail(&self) -> bool {
+        self.failed_end.is_some_and(|end| end >= self.file_len)
     }
 
     pub(super) fn next<T: DeserializeOwned>(&mut self) -> Result<Option<(LogIndex, T)>, Error> {
-        let pos = self.0.pos();
-        match bincode::deserialize_from(&mut self.0) {
-            Ok(entry) => {
-                let len = self.0.pos() - pos;
-                let index = LogIndex { len, pos };
-                Ok(Some((index, entry)))
-            }
-            Err(e) => match e.as_ref() {
-                bincode::ErrorKind::Io(ioe) => match ioe.kind() {
-                    io::ErrorKind::UnexpectedEof => Ok(None),
-                    _ => Err(e.into()),
-                },
-                _ => Err(e.into()),
-            },
+        let pos = self.reader.pos();
+        let corruption = Error::Corruption {
+            fileid: self.fileid,
+            pos,
+        };
+        if self.failed_end.is_some() {
+            return Err(corruption);
+        }
+        let remaining = self.file_len.saturating_sub(pos);
+        if remaining == 0 {
+            return Ok(None);
         }
+        if remaining < HEADER_LEN as u64 {
+            self.failed_end = Some(pos + HEADER_LEN as u64);
+            return Err(corruption);
+        }
+        let mut header = [0; HEADER_LEN];
+        self.reader.read_exact(&mut header)?;
+        let payload_len = u64::from_le_bytes(header[4..].try_into().unwrap());
+        let end = (pos + HEADER_LEN as u64).saturating_add(payload_len);
+        if end > self.file_len {
+            self.failed_end = Some(end);
+            return Err(corruption);
+        }
+
+        let mut record = header.to_vec();
+        record.resize(HEADER_LEN + payload_len as usize, 0);
+        self.reader.read_exact(&mut record[HEADER_LEN..])?;
+        let Some(payload) = verify(&record) else {
+            self.failed_end = Some(end);
+            return Err(corruption);
+        };
+        let entry = bincode::deserialize(payload)?;
+        let len = self.reader.pos() - pos;
+        Ok(Some((LogIndex { len, pos }, entry)))
     }
 }
 
+/// Checks the header of the file at `path`, the data or hint file `fileid`.
+pub(super) fn check_header<P: AsRef<Path>>(path: P, fileid: u64) -> Result<(), Error> {
+    let mut header = Vec::with_capacity(FILE_HEADER_LEN as usize);
+    open(path)?.take(FILE_HEADER_LEN).read_to_end(&mut header)?;
+    parse_file_header(&header, fileid).map(|_| ())
+}
+
 pub(super) fn create<P: AsRef<Path>>(path: P) -> io::Result<fs::File> {
     fs::OpenOptions::new()
         .append(true)
@@ -219,6 +534,27 @@ pub(super) fn create<P: AsRef<Path>>(path: P) -> io::Result<fs::File> {
         .open(path)
 }
 
+/// Writes `entries` through a temporary file that is synced and renamed into
+/// place, so `path` either holds all of them or does not exist.
+pub(super) fn write_atomic<T, P>(path: P, entries: &[T]) -> Result<(), Error>
+where
+    T: Serialize,
+    P: AsRef<Path>,
+{
+    let tmpfile = utils::tmpfile_name(&path);
+    let mut writer = LogWriter::new(create(&tmpfile)?)?;
+    writer.append_all(entries)?;
+    writer.sync()?;
+    fs::rename(tmpfile, path)?;
+    Ok(())
+}
+
+pub(super) fn truncate<P: AsRef<Path>>(path: P, len: u64) -> io::Result<()> {
+    let file = fs::OpenOptions::new().write(true).open(path)?;
+    file.set_len(len)?;
+    file.sync_all()
+}
+
 pub(super) fn open<P: AsRef<Path>>(path: P) -> io::Result<fs::File> {
     fs::OpenOptions::new().read(true).open(path)
 }
@@ -0,0 +1,359 @@
+use std::{collections::HashSet, fs, ops::Range, path::Path, sync::Arc};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    context::{Context, KeyDirEntry},
+    log::{self, FileKind, LogDir, LogIterator, LogWriter},
+    utils,
+    writer::Writer,
+    BatchMarker, DataFileEntry, Error, HintFileEntry,
+};
+
+#[derive(Debug)]
+pub(super) struct MergedEntry {
+    pub(super) keyspace: u32,
+    pub(super) key: Bytes,
+    pub(super) fileid: u64,
+    pub(super) pos: u64,
+    pub(super) keydir_entry: KeyDirEntry,
+}
+
+/// A data file written by the writer whose records a merge output holds
+/// copies of, along with its length when it was merged.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct MergedFile {
+    pub(super) fileid: u64,
+    pub(super) len: u64,
+}
+
+#[derive(Debug)]
+pub(super) struct Merger {
+    ctx: Arc<Context>,
+    readers: LogDir,
+}
+
+impl Merger {
+    pub(super) fn new(ctx: Arc<Context>, readers: LogDir) -> Self {
+        Self { ctx, readers }
+    }
+
+    /// Merges every sealed data file, including the one active when the
+    /// merge starts.
+    pub(super) fn merge(&mut self, writer: &Mutex<Writer>) -> Result<(), Error> {
+        self.merge_with(writer, |writer, fileids| {
+            if writer.is_empty() && fileids.iter().all(|&id| id == writer.active_fileid()) {
+                Vec::new()
+            } else {
+                fileids.to_vec()
+            }
+        })
+    }
+
+    /// Merges the sealed data files whose fragmentation or dead bytes reach
+    /// the given thresholds.
+    pub(super) fn merge_fragmented(
+        &mut self,
+        writer: &Mutex<Writer>,
+        min_fragmentation: f64,
+        min_dead_bytes: u64,
+    ) -> Result<(), Error> {
+        self.merge_with(writer, |writer, fileids| {
+            fileids
+                .iter()
+                .copied()
+                .filter(|&id| id < writer.active_fileid())
+                .filter(|id| {
+                    writer.get_stats().total(*id).is_some_and(|stats| {
+                        stats.fragmentation() >= min_fragmentation
+                            || stats.dead_bytes() >= min_dead_bytes
+                    })
+                })
+                .collect()
+        })
+    }
+
+    /// Merges the files picked by `select` out of all data files up to and
+    /// including the active one.
+    fn merge_with<F>(&mut self, writer: &Mutex<Writer>, select: F) -> Result<(), Error>
+    where
+        F: FnOnce(&Writer, &[u64]) -> Vec<u64>,
+    {
+        let (fileids, oldest_unmerged, output_fileids) = {
+            let mut writer = writer.lock();
+            let active_fileid = writer.active_fileid();
+            let all_fileids: Vec<u64> = utils::sorted_fileids(&self.ctx.path)?
+                .filter(|&id| id <= active_fileid)
+                .collect();
+            let fileids = select(&writer, &all_fileids);
+            if fileids.is_empty() {
+                return Ok(());
+            }
+            let oldest_unmerged = all_fileids.iter().copied().find(|id| !fileids.contains(id));
+            // Merged files never hold more live data than their inputs, so one
+            // output file per input (plus one for overflow) is always enough.
+            let output_fileids = writer.reserve_fileids(fileids.len() as u64 + 1)?;
+            (fileids, oldest_unmerged, output_fileids)
+        };
+
+        let marker = self.marker(&fileids)?;
+        let output_bytes =
+            self.copy_entries(writer, &fileids, oldest_unmerged, output_fileids, marker)?;
+
+        writer.lock().finish_merge(&fileids);
+        let mut input_bytes = 0;
+        for &fileid in &fileids {
+            input_bytes +=
+                utils::file_size(utils::datafile_name(&self.ctx.path, fileid))?.unwrap_or_default();
+            self.ctx.retire(fileid)?;
+        }
+        self.readers.clear();
+        self.ctx.bump_epoch();
+        self.ctx
+            .metrics
+            .merged(input_bytes.saturating_sub(output_bytes));
+
+        Ok(())
+    }
+
+    /// Builds the record that heads the outputs of merging `fileids`. Inputs
+    /// that are merge outputs themselves are replaced by the files they list,
+    /// so that the marker always names files written by the writer.
+    fn marker(&self, fileids: &[u64]) -> Result<DataFileEntry, Error> {
+        let mut merged = Vec::new();
+        for &fileid in fileids {
+            // The active data file is removed when it is sealed empty.
+            let Some(len) = utils::file_size(utils::datafile_name(&self.ctx.path, fileid))? else {
+                continue;
+            };
+            match merged_files(&self.ctx.path, fileid)? {
+                Some(files) => merged.extend(files),
+                None => merged.push(MergedFile { fileid, len }),
+            }
+        }
+        Ok(DataFileEntry {
+            key: Bytes::from(bincode::serialize(&merged)?),
+            ..DataFileEntry::marker(0, utils::timestamp(), BatchMarker::Merge)
+        })
+    }
+
+    fn copy_entries(
+        &mut self,
+        writer: &Mutex<Writer>,
+        fileids: &[u64],
+        oldest_unmerged: Option<u64>,
+        mut output_fileids: Range<u64>,
+        marker: DataFileEntry,
+    ) -> Result<u64, Error> {
+        let inputs: HashSet<u64> = fileids.iter().copied().collect();
+        let mut output =
+            MergeOutput::create(&self.ctx, output_fileids.next().unwrap(), Some(marker))?;
+        let now = utils::timestamp();
+        let mut output_bytes = 0;
+
+        // The values of dropped keyspaces are left behind.
+        for keydir in self.ctx.keydirs().live() {
+            for entry in keydir.entries().iter() {
+                let keydir_entry = entry.value();
+                if !inputs.contains(&keydir_entry.fileid) {
+                    continue;
+                }
+                // Expired values are dropped here and kept below, where needed,
+                // along with the tombstones.
+                if utils::is_expired(keydir_entry.expires, now) {
+                    keydir.expire(
+                        entry.key(),
+                        keydir_entry.fileid,
+                        keydir_entry.pos,
+                        writer.lock().get_stats_mut(),
+                    );
+                    continue;
+                }
+                output =
+                    output.rotate(&self.ctx, writer, &mut output_fileids, &mut output_bytes)?;
+                let index = unsafe {
+                    output.writer.append_raw(
+                        &mut self.readers,
+                        &self.ctx.path,
+                        keydir_entry.fileid,
+                        keydir_entry.len,
+                        keydir_entry.pos,
+                    )?
+                };
+                output.written_bytes += index.len;
+                output.hints.push(HintFileEntry {
+                    tstamp: keydir_entry.tstamp,
+                    keyspace: keydir.id(),
+                    len: index.len,
+                    pos: index.pos,
+                    key: entry.key().clone(),
+                    tombstone: false,
+                    expires: keydir_entry.expires,
+                });
+                output.entries.push(MergedEntry {
+                    keyspace: keydir.id(),
+                    key: entry.key().clone(),
+                    fileid: keydir_entry.fileid,
+                    pos: keydir_entry.pos,
+                    keydir_entry: KeyDirEntry {
+                        fileid: output.fileid,
+                        len: index.len,
+                        pos: index.pos,
+                        tstamp: keydir_entry.tstamp,
+                        expires: keydir_entry.expires,
+                    },
+                });
+            }
+        }
+
+        // Tombstones and expired values can only be dropped once every older
+        // file is merged too, otherwise the values they shadow would come back
+        // on recovery.
+        for &fileid in fileids {
+            if oldest_unmerged.is_none_or(|id| id > fileid) {
+                continue;
+            }
+            let file = log::open(utils::datafile_name(&self.ctx.path, fileid))?;
+            let mut datafile_iter = LogIterator::new(file, fileid)?;
+            while let Some((_, datafile_entry)) = datafile_iter.next::<DataFileEntry>()? {
+                let shadows = datafile_entry.value.is_none()
+                    || utils::is_expired(datafile_entry.expires, now);
+                // Nothing of a dropped keyspace comes back on recovery, so its
+                // tombstones are not needed either.
+                let keydir = self.ctx.keydirs().get(datafile_entry.keyspace);
+                if !shadows
+                    || datafile_entry.batch.is_some()
+                    || keydir.is_dropped()
+                    || keydir.entries().contains_key(&datafile_entry.key)
+                {
+                    continue;
+                }
+                output =
+                    output.rotate(&self.ctx, writer, &mut output_fileids, &mut output_bytes)?;
+                // An expired value is kept as a tombstone, which shadows the
+                // same values without its bytes.
+                let tombstone = DataFileEntry::new(
+                    datafile_entry.keyspace,
+                    datafile_entry.tstamp,
+                    datafile_entry.key,
+                    None,
+                );
+                let index = output.writer.append(&tombstone)?;
+                output.written_bytes += index.len;
+                output.hints.push(HintFileEntry {
+                    tstamp: tombstone.tstamp,
+                    keyspace: tombstone.keyspace,
+                    len: index.len,
+                    pos: index.pos,
+                    key: tombstone.key,
+                    tombstone: true,
+                    expires: None,
+                });
+            }
+        }
+
+        output_bytes += output.commit(&self.ctx, writer)?;
+        Ok(output_bytes)
+    }
+}
+
+/// A data file being filled by a merge. It is written under a temporary name
+/// and only renamed into place once it is complete and synced.
+struct MergeOutput {
+    fileid: u64,
+    writer: LogWriter,
+    written_bytes: u64,
+    entries: Vec<MergedEntry>,
+    hints: Vec<HintFileEntry>,
+}
+
+impl MergeOutput {
+    /// Starts the output `fileid`, headed by `marker` if it is the first one.
+    fn create(ctx: &Context, fileid: u64, marker: Option<DataFileEntry>) -> Result<Self, Error> {
+        let tmpfile = utils::tmpfile_name(utils::datafile_name(&ctx.path, fileid));
+        let mut writer = LogWriter::with_kind(log::create(tmpfile)?, FileKind::Merged)?;
+        if let Some(marker) = marker {
+            writer.append(&marker)?;
+        }
+        Ok(Self {
+            fileid,
+            written_bytes: writer.len(),
+            writer,
+            entries: Vec::new(),
+            hints: Vec::new(),
+        })
+    }
+
+    /// Commits this output and starts the next one once it is full, unless
+    /// the reserved file ids are used up.
+    fn rotate(
+        self,
+        ctx: &Context,
+        writer: &Mutex<Writer>,
+        fileids: &mut Range<u64>,
+        output_bytes: &mut u64,
+    ) -> Result<Self, Error> {
+        // Something is copied into every output before it counts as full, no
+        // matter how large its marker is.
+        if self.written_bytes <= ctx.max_file_size || self.hints.is_empty() {
+            return Ok(self);
+        }
+        match fileids.next() {
+            Some(fileid) => {
+                // The first output alone lists the merged files. It is never
+                // empty, since the next one is only started once it is full.
+                let next = Self::create(ctx, fileid, None)?;
+                *output_bytes += self.commit(ctx, writer)?;
+                Ok(next)
+            }
+            None => Ok(self),
+        }
+    }
+
+    /// Moves this output into place and returns its size.
+    fn commit(mut self, ctx: &Context, writer: &Mutex<Writer>) -> Result<u64, Error> {
+        let datafile = utils::datafile_name(&ctx.path, self.fileid);
+        let tmpfile = utils::tmpfile_name(&datafile);
+        // Nothing was copied besides the marker.
+        if self.hints.is_empty() {
+            fs::remove_file(tmpfile)?;
+            return Ok(0);
+        }
+        self.writer.sync()?;
+        fs::rename(tmpfile, datafile)?;
+        log::write_atomic(utils::hintfile_name(&ctx.path, self.fileid), &self.hints)?;
+        let tombstones = self
+            .hints
+            .iter()
+            .filter(|hint| hint.tombstone)
+            .map(|hint| (hint.keyspace, hint.len))
+            .collect();
+        writer
+            .lock()
+            .commit_merge(self.fileid, self.entries, tombstones);
+        Ok(self.written_bytes)
+    }
+}
+
+/// Returns the files listed by the marker heading the data file `fileid`, an
+/// empty list for the later outputs of a merge, which have none, or `None` if
+/// it was not written by a merge.
+pub(super) fn merged_files<P: AsRef<Path>>(
+    path: P,
+    fileid: u64,
+) -> Result<Option<Vec<MergedFile>>, Error> {
+    let file = log::open(utils::datafile_name(&path, fileid))?;
+    let mut datafile_iter = LogIterator::new(file, fileid)?;
+    if datafile_iter.kind() != Some(FileKind::Merged) {
+        return Ok(None);
+    }
+    match datafile_iter.next::<DataFileEntry>()? {
+        Some((_, entry)) if entry.batch == Some(BatchMarker::Merge) => {
+            Ok(Some(bincode::deserialize(&entry.key)?))
+        }
+        // Only the first output of a merge lists the merged files.
+        _ => Ok(Some(Vec::new())),
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::Error;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 14] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5,
+    1.0, 5.0,
+];
+
+/// Operations whose calls are counted and timed.
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Op {
+    Get,
+    Put,
+    Del,
+    Cas,
+    Batch,
+    Sync,
+}
+
+impl Op {
+    const ALL: [Op; 6] = [Op::Get, Op::Put, Op::Del, Op::Cas, Op::Batch, Op::Sync];
+
+    fn name(self) -> &'static str {
+        match self {
+            Op::Get => "get",
+            Op::Put => "put",
+            Op::Del => "del",
+            Op::Cas => "cas",
+            Op::Batch => "batch",
+            Op::Sync => "sync",
+        }
+    }
+}
+
+/// Operation-level metrics of a store, returned by
+/// [`Handle::metrics`](crate::Handle::metrics).
+///
+/// Everything is counted from the moment the store is opened. Calls through
+/// an [`AsyncHandle`](crate::AsyncHandle) are included.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    ops: [OpMetrics; Op::ALL.len()],
+    /// Time `get` spends waiting for a free reader.
+    reader_wait: Histogram,
+    /// Time writes and syncs spend waiting for the writer lock.
+    writer_wait: Histogram,
+    rotations: AtomicU64,
+    appended_bytes: AtomicU64,
+    merges: AtomicU64,
+    reclaimed_bytes: AtomicU64,
+    syncs: AtomicU64,
+    unsynced_bytes: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct OpMetrics {
+    errors: AtomicU64,
+    latency: Histogram,
+}
+
+impl Metrics {
+    /// Runs `f` as one call of `op`, recording how long it took and whether
+    /// it failed.
+    pub(super) fn time<T>(&self, op: Op, f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+        self.time_since(op, Instant::now(), f)
+    }
+
+    /// Like [`time`](Self::time), for a call that started at `start`, such as
+    /// one that had to wait before `f` could run.
+    pub(super) fn time_since<T>(
+        &self,
+        op: Op,
+        start: Instant,
+        f: impl FnOnce() -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let result = f();
+        let op = &self.ops[op as usize];
+        op.latency.observe(start.elapsed());
+        if result.is_err() {
+            op.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    pub(super) fn reader_wait(&self, wait: Duration) {
+        self.reader_wait.observe(wait);
+    }
+
+    pub(super) fn writer_wait(&self, wait: Duration) {
+        self.writer_wait.observe(wait);
+    }
+
+    pub(super) fn rotation(&self) {
+        self.rotations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn appended(&self, nbytes: u64) {
+        self.appended_bytes.fetch_add(nbytes, Ordering::Relaxed);
+    }
+
+    pub(super) fn synced(&self) {
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn unsynced(&self, nbytes: u64) {
+        self.unsynced_bytes.store(nbytes, Ordering::Relaxed);
+    }
+
+    pub(super) fn merged(&self, reclaimed_bytes: u64) {
+        self.merges.fetch_add(1, Ordering::Relaxed);
+        self.reclaimed_bytes
+            .fetch_add(reclaimed_bytes, Ordering::Relaxed);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format, ready
+    /// to be served to a scraper.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "bitcask_operations_total",
+            "counter",
+            "Calls of each operation.",
+        );
+        for op in Op::ALL {
+            let count = self.ops[op as usize].latency.count();
+            line(&mut out, "bitcask_operations_total", &op_label(op), count);
+        }
+        header(
+            &mut out,
+            "bitcask_operation_errors_total",
+            "counter",
+            "Calls of each operation that returned an error.",
+        );
+        for op in Op::ALL {
+            let errors = self.ops[op as usize].errors.load(Ordering::Relaxed);
+            line(
+                &mut out,
+                "bitcask_operation_errors_total",
+                &op_label(op),
+                errors,
+            );
+        }
+        header(
+            &mut out,
+            "bitcask_operation_duration_seconds",
+            "histogram",
+            "Latency of each operation.",
+        );
+        for op in Op::ALL {
+            self.ops[op as usize].latency.render(
+                &mut out,
+                "bitcask_operation_duration_seconds",
+                &op_label(op),
+            );
+        }
+
+        header(
+            &mut out,
+            "bitcask_reader_wait_seconds",
+            "histogram",
+            "Time reads waited for a free reader.",
+        );
+        self.reader_wait
+            .render(&mut out, "bitcask_reader_wait_seconds", "");
+        header(
+            &mut out,
+            "bitcask_writer_wait_seconds",
+            "histogram",
+            "Time writes waited for the writer lock.",
+        );
+        self.writer_wait
+            .render(&mut out, "bitcask_writer_wait_seconds", "");
+
+        let counters = [
+            (
+                "bitcask_rotations_total",
+                "Data files started after the active one filled up or was sealed.",
+                &self.rotations,
+            ),
+            (
+                "bitcask_appended_bytes_total",
+                "Bytes appended to data files by writes.",
+                &self.appended_bytes,
+            ),
+            (
+                "bitcask_merges_total",
+                "Merges that rewrote at least one data file.",
+                &self.merges,
+            ),
+            (
+                "bitcask_merge_reclaimed_bytes_total",
+                "Disk space freed by merges.",
+                &self.reclaimed_bytes,
+            ),
+            (
+                "bitcask_syncs_total",
+                "Times data files were flushed to disk with fsync.",
+                &self.syncs,
+            ),
+        ];
+        for (name, help, value) in counters {
+            header(&mut out, name, "counter", help);
+            line(&mut out, name, "", value.load(Ordering::Relaxed));
+        }
+        header(
+            &mut out,
+            "bitcask_unsynced_bytes",
+            "gauge",
+            "Bytes appended to the active data file since it was last synced.",
+        );
+        line(
+            &mut out,
+            "bitcask_unsynced_bytes",
+            "",
+            self.unsynced_bytes.load(Ordering::Relaxed),
+        );
+
+        out
+    }
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket, the last one counting those above every
+    /// bound. Rendering makes them cumulative.
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|&bound| secs <= bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    fn count(&self) -> u64 {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Writes the samples of this histogram, adding `labels` (`key="value"`
+    /// pairs without braces) to each of them.
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let le = BUCKETS.get(i).map_or("+Inf".to_string(), f64::to_string);
+            let labels = format!("{labels}{sep}le=\"{le}\"");
+            line(out, &format!("{name}_bucket"), &labels, cumulative);
+        }
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        line(out, &format!("{name}_sum"), labels, sum);
+        line(out, &format!("{name}_count"), labels, cumulative);
+    }
+}
+
+fn op_label(op: Op) -> String {
+    format!("op=\"{}\"", op.name())
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn line(out: &mut String, name: &str, labels: &str, value: impl std::fmt::Display) {
+    if labels.is_empty() {
+        let _ = writeln!(out, "{name} {value}");
+    } else {
+        let _ = writeln!(out, "{name}{{{labels}}} {value}");
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use crate::{task, CompactionOptions, SyncPolicy};
+
+/// Settings for [`Bitcask::open_with`](crate::Bitcask::open_with).
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub(super) max_file_size: u64,
+    pub(super) readers: usize,
+    pub(super) reader_cache_size: usize,
+    pub(super) sync_policy: SyncPolicy,
+    pub(super) compaction: CompactionOptions,
+    pub(super) create_if_missing: bool,
+    pub(super) error_if_exists: bool,
+    pub(super) read_only: bool,
+    pub(super) refresh_interval: Option<Duration>,
+    pub(super) subscription_capacity: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_file_size: 16 * 1024 * 1024,
+            readers: 4,
+            reader_cache_size: 16,
+            sync_policy: SyncPolicy::default(),
+            compaction: CompactionOptions::default(),
+            create_if_missing: true,
+            error_if_exists: false,
+            read_only: false,
+            refresh_interval: None,
+            subscription_capacity: 1024,
+        }
+    }
+}
+
+impl Options {
+    /// Size after which the active data file is sealed and a new one started.
+    pub fn max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Number of concurrent readers, at least one.
+    pub fn readers(mut self, readers: usize) -> Self {
+        self.readers = readers;
+        self
+    }
+
+    /// Number of memory-mapped data files each reader keeps open, at least
+    /// one.
+    pub fn reader_cache_size(mut self, reader_cache_size: usize) -> Self {
+        self.reader_cache_size = reader_cache_size;
+        self
+    }
+
+    /// When writes are synced. An [`Interval`](SyncPolicy::Interval) below a
+    /// millisecond is raised to one.
+    pub fn sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.sync_policy = match sync_policy {
+            SyncPolicy::Interval(interval) => SyncPolicy::Interval(interval.max(task::MIN_PERIOD)),
+            sync_policy => sync_policy,
+        };
+        self
+    }
+
+    pub fn compaction(mut self, compaction: CompactionOptions) -> Self {
+        self.compaction = compaction;
+        self
+    }
+
+    /// Create the directory if it does not exist yet.
+    pub fn create_if_missing(mut self, create_if_missing: bool) -> Self {
+        self.create_if_missing = create_if_missing;
+        self
+    }
+
+    /// Fail with [`Error::AlreadyExists`](crate::Error::AlreadyExists) if the
+    /// directory already holds data files.
+    pub fn error_if_exists(mut self, error_if_exists: bool) -> Self {
+        self.error_if_exists = error_if_exists;
+        self
+    }
+
+    /// Open without a writer. Writes fail with
+    /// [`Error::ReadOnly`](crate::Error::ReadOnly) and no data file is
+    /// created, repaired or removed.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// In read-only mode, pick up what another process writes to the store
+    /// every `interval`, at least every millisecond. Without it the keydir
+    /// only changes on [`Handle::refresh`](crate::Handle::refresh).
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = Some(interval.max(task::MIN_PERIOD));
+        self
+    }
+
+    /// Number of events buffered for
+    /// [`Handle::subscribe`](crate::Handle::subscribe) before the slowest
+    /// subscriber starts missing them, at least one.
+    pub fn subscription_capacity(mut self, capacity: usize) -> Self {
+        self.subscription_capacity = capacity;
+        self
+    }
+}
@@ -1,34 +1,66 @@
-use std::{cell::RefCell, sync::Arc};
+use std::{
+    cell::{Cell, RefCell},
+    io,
+    sync::Arc,
+};
 
 use bytes::Bytes;
 
-use crate::{context::Context, log::LogDir, DataFileEntry, Error};
+use crate::{
+    context::{Context, Keydir},
+    log::LogDir,
+    utils, DataFileEntry, Error,
+};
 
 #[derive(Debug)]
 pub(super) struct Reader {
     ctx: Arc<Context>,
     readers: RefCell<LogDir>,
+    epoch: Cell<u64>,
 }
 
 impl Reader {
     pub(super) fn new(ctx: Arc<Context>, readers: RefCell<LogDir>) -> Self {
-        Self { ctx, readers }
+        let epoch = Cell::new(ctx.epoch());
+        Self {
+            ctx,
+            readers,
+            epoch,
+        }
     }
 
-    pub(super) fn get(&self, key: Bytes) -> Result<Option<Bytes>, Error> {
-        match self.ctx.get_keydir().get(&key) {
-            Some(keydir_entry) => {
-                let datafile_entry = unsafe {
-                    self.readers.borrow_mut().read::<DataFileEntry, _>(
-                        &self.ctx.path,
-                        keydir_entry.value().fileid,
-                        keydir_entry.value().len,
-                        keydir_entry.value().pos,
-                    )?
-                };
-                Ok(datafile_entry.value)
+    pub(super) fn get(&self, keydir: &Keydir, key: Bytes) -> Result<Option<Bytes>, Error> {
+        let epoch = self.ctx.epoch();
+        if self.epoch.replace(epoch) != epoch {
+            self.readers.borrow_mut().clear();
+        }
+
+        loop {
+            let Some(keydir_entry) = keydir.get(&key) else {
+                return Ok(None);
+            };
+            if utils::is_expired(keydir_entry.value().expires, utils::timestamp()) {
+                return Ok(None);
+            }
+            let result = unsafe {
+                self.readers.borrow_mut().read::<DataFileEntry, _>(
+                    &self.ctx.path,
+                    keydir_entry.value().fileid,
+                    keydir_entry.value().len,
+                    keydir_entry.value().pos,
+                )
+            };
+            match result {
+                Ok(datafile_entry) => return Ok(datafile_entry.value),
+                // A merge removed the file after the lookup; by then the keydir
+                // points at the merged copy, so look the key up again.
+                Err(Error::Io(ref e))
+                    if e.kind() == io::ErrorKind::NotFound
+                        && keydir
+                            .get(&key)
+                            .is_none_or(|e| e.value().fileid != keydir_entry.value().fileid) => {}
+                Err(e) => return Err(e),
             }
-            None => Ok(None),
         }
     }
 }
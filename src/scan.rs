@@ -0,0 +1,128 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+
+use crate::{context::Keydir, utils, Error, Handle};
+
+/// Walks the keydir between two bounds, one key at a time.
+///
+/// The cursor only remembers the last key it returned rather than borrowing
+/// the keydir, so keys written or deleted while it runs are seen as long as
+/// the cursor has not passed them yet. Expired keys are skipped.
+#[derive(Debug)]
+pub(super) struct Cursor {
+    start: Bound<Bytes>,
+    end: Bound<Bytes>,
+    rev: bool,
+}
+
+impl Cursor {
+    pub(super) fn new(start: Bound<Bytes>, end: Bound<Bytes>, rev: bool) -> Self {
+        Self { start, end, rev }
+    }
+
+    pub(super) fn next_key(&mut self, keydir: &Keydir) -> Option<Bytes> {
+        if keydir.is_dropped() {
+            return None;
+        }
+        let bounds = (
+            self.start.as_ref().map(Bytes::as_ref),
+            self.end.as_ref().map(Bytes::as_ref),
+        );
+        let now = utils::timestamp();
+        let key = keydir.with(|entries| {
+            let mut range = entries
+                .range::<[u8], _>(bounds)
+                .filter(|entry| !utils::is_expired(entry.value().expires, now));
+            let entry = if self.rev {
+                range.next_back()
+            } else {
+                range.next()
+            }?;
+            Some(entry.key().clone())
+        })?;
+        if self.rev {
+            self.end = Bound::Excluded(key.clone());
+        } else {
+            self.start = Bound::Excluded(key.clone());
+        }
+        Some(key)
+    }
+}
+
+/// Returns the bounds covering every key that starts with `prefix`.
+pub(super) fn prefix_bounds(prefix: Bytes) -> (Bound<Bytes>, Bound<Bytes>) {
+    // The first key past the prefix is the prefix with its last byte that is
+    // not 0xff incremented and everything after it dropped.
+    let end = match prefix.iter().rposition(|&b| b != u8::MAX) {
+        Some(i) => {
+            let mut end = prefix[..=i].to_vec();
+            end[i] += 1;
+            Bound::Excluded(Bytes::from(end))
+        }
+        None => Bound::Unbounded,
+    };
+    (Bound::Included(prefix), end)
+}
+
+/// Iterator over the key-value pairs in a range of keys, in key order or in
+/// reverse, returned by [`Handle::range`] and [`Handle::range_rev`].
+///
+/// Values are read lazily as the iterator advances. A key deleted after the
+/// cursor found it but before its value was read is skipped. The iterator
+/// stops after yielding an error.
+#[derive(Debug)]
+pub struct Range {
+    handle: Handle,
+    cursor: Option<Cursor>,
+}
+
+impl Range {
+    pub(super) fn new(handle: Handle, cursor: Cursor) -> Self {
+        Self {
+            handle,
+            cursor: Some(cursor),
+        }
+    }
+}
+
+impl Iterator for Range {
+    type Item = Result<(Bytes, Bytes), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = self.cursor.as_mut()?.next_key(&self.handle.keydir)?;
+            match self.handle.get(key.clone()) {
+                Ok(Some(value)) => return Some(Ok((key, value))),
+                Ok(None) => continue,
+                Err(e) => {
+                    self.cursor = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the keys that start with a prefix, in key order, returned by
+/// [`Handle::keys`]. Only the keydir is consulted, never the data files.
+#[derive(Debug)]
+pub struct Keys {
+    handle: Handle,
+    cursor: Cursor,
+}
+
+impl Keys {
+    pub(super) fn new(handle: Handle, cursor: Cursor) -> Self {
+        Self { handle, cursor }
+    }
+}
+
+impl Iterator for Keys {
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.cursor.next_key(&self.handle.keydir)?;
+        Some(key)
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::{btree_map, BTreeMap, HashMap},
+    ops::RangeBounds,
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::{
+    context::{Context, KeyDirEntry, Keydir},
+    log::{self, LogReader},
+    utils, DataFileEntry, Error,
+};
+
+/// A read-only view of the store as of the moment it was taken, returned by
+/// [`Handle::snapshot`](crate::Handle::snapshot).
+///
+/// Taking a snapshot copies the keydir and opens every data file it points
+/// into. Until the snapshot is dropped, merges leave those files in place.
+#[derive(Debug)]
+pub struct Snapshot {
+    ctx: Arc<Context>,
+    keydir: BTreeMap<Bytes, KeyDirEntry>,
+    fileids: Vec<u64>,
+    readers: Mutex<HashMap<u64, LogReader>>,
+}
+
+impl Snapshot {
+    /// Copies `keydir`, which the caller keeps from changing.
+    pub(super) fn new(ctx: Arc<Context>, keydir: &Keydir) -> Result<Self, Error> {
+        let now = utils::timestamp();
+        let keydir: BTreeMap<_, _> = if keydir.is_dropped() {
+            BTreeMap::new()
+        } else {
+            keydir
+                .entries()
+                .iter()
+                .filter(|entry| !utils::is_expired(entry.value().expires, now))
+                .map(|entry| (entry.key().clone(), entry.value().clone()))
+                .collect()
+        };
+        let mut fileids: Vec<u64> = keydir.values().map(|entry| entry.fileid).collect();
+        fileids.sort_unstable();
+        fileids.dedup();
+
+        ctx.pin(&fileids);
+        let snapshot = Self {
+            ctx,
+            keydir,
+            fileids,
+            readers: Mutex::default(),
+        };
+        {
+            let mut readers = snapshot.readers.lock();
+            for &fileid in &snapshot.fileids {
+                let file = log::open(utils::datafile_name(&snapshot.ctx.path, fileid))?;
+                readers.insert(fileid, LogReader::new(file, fileid)?);
+            }
+        }
+        Ok(snapshot)
+    }
+
+    pub fn get(&self, key: Bytes) -> Result<Option<Bytes>, Error> {
+        match self.keydir.get(&key) {
+            Some(keydir_entry) => self.read(keydir_entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Iterates over the entries whose keys fall in `range`, in key order.
+    pub fn range<R: RangeBounds<Bytes>>(&self, range: R) -> SnapshotRange<'_> {
+        SnapshotRange {
+            snapshot: self,
+            range: self.keydir.range(range),
+            rev: false,
+        }
+    }
+
+    /// Iterates over the entries whose keys fall in `range`, in reverse key
+    /// order.
+    pub fn range_rev<R: RangeBounds<Bytes>>(&self, range: R) -> SnapshotRange<'_> {
+        SnapshotRange {
+            snapshot: self,
+            range: self.keydir.range(range),
+            rev: true,
+        }
+    }
+
+    fn read(&self, keydir_entry: &KeyDirEntry) -> Result<Bytes, Error> {
+        let mut readers = self.readers.lock();
+        let reader = readers
+            .get_mut(&keydir_entry.fileid)
+            .expect("data file opened with the snapshot");
+        let datafile_entry =
+            unsafe { reader.at::<DataFileEntry>(keydir_entry.len, keydir_entry.pos)? };
+        datafile_entry.value.ok_or(Error::Corruption {
+            fileid: keydir_entry.fileid,
+            pos: keydir_entry.pos,
+        })
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        // Unmap the files before a retired one is removed.
+        self.readers.get_mut().clear();
+        if let Err(e) = self.ctx.unpin(&self.fileids) {
+            eprintln!("failed to remove data files released by a snapshot - {e}");
+        }
+    }
+}
+
+/// Iterator over the key-value pairs of a [`Snapshot`] in a range of keys,
+/// returned by [`Snapshot::range`] and [`Snapshot::range_rev`].
+#[derive(Debug)]
+pub struct SnapshotRange<'a> {
+    snapshot: &'a Snapshot,
+    range: btree_map::Range<'a, Bytes, KeyDirEntry>,
+    rev: bool,
+}
+
+impl Iterator for SnapshotRange<'_> {
+    type Item = Result<(Bytes, Bytes), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, keydir_entry) = if self.rev {
+            self.range.next_back()
+        } else {
+            self.range.next()
+        }?;
+        Some(
+            self.snapshot
+                .read(keydir_entry)
+                .map(|value| (key.clone(), value)),
+        )
+    }
+}
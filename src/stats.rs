@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use crate::{context::Context, log::LogStatistics, utils, Error};
+
+/// Statistics of a store, returned by [`Handle::stats`](crate::Handle::stats).
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Stats {
+    /// One entry per data file, oldest first.
+    pub files: Vec<FileStats>,
+    /// Number of keys in the keyspace of the handle.
+    pub keys: u64,
+    /// Id of the data file being written, unless the store is read-only.
+    pub active_fileid: Option<u64>,
+    /// Bytes written to the active data file so far.
+    pub active_bytes: u64,
+    /// Size of all data and hint files together.
+    pub disk_size: u64,
+    pub live_keys: u64,
+    pub dead_keys: u64,
+    pub dead_bytes: u64,
+}
+
+/// Statistics of one data file.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct FileStats {
+    pub fileid: u64,
+    pub size: u64,
+    /// Size of the hint file, zero if there is none.
+    pub hint_size: u64,
+    /// Entries still pointed to by the keydir.
+    pub live_keys: u64,
+    /// Overwritten or deleted entries, and tombstones.
+    pub dead_keys: u64,
+    pub dead_bytes: u64,
+    /// Share of dead entries among all entries, which compaction goes by.
+    pub fragmentation: f64,
+}
+
+/// Puts the per-file `stats` kept by the writer or follower together with
+/// what is on disk.
+pub(super) fn collect(
+    ctx: &Context,
+    keys: u64,
+    stats: &HashMap<u64, LogStatistics>,
+    active: Option<(u64, u64)>,
+) -> Result<Stats, Error> {
+    let mut files = Vec::new();
+    for fileid in utils::sorted_fileids(&ctx.path)? {
+        // A merge may remove a file after it was listed.
+        let Some(size) = utils::file_size(utils::datafile_name(&ctx.path, fileid))? else {
+            continue;
+        };
+        let hint_size =
+            utils::file_size(utils::hintfile_name(&ctx.path, fileid))?.unwrap_or_default();
+        let file_stats = stats.get(&fileid);
+        files.push(FileStats {
+            fileid,
+            size,
+            hint_size,
+            live_keys: file_stats.map_or(0, LogStatistics::live_keys),
+            dead_keys: file_stats.map_or(0, LogStatistics::dead_keys),
+            dead_bytes: file_stats.map_or(0, LogStatistics::dead_bytes),
+            fragmentation: file_stats.map_or(0.0, LogStatistics::fragmentation),
+        });
+    }
+
+    Ok(Stats {
+        keys,
+        active_fileid: active.map(|(fileid, _)| fileid),
+        active_bytes: active.map_or(0, |(_, written_bytes)| written_bytes),
+        disk_size: files.iter().map(|file| file.size + file.hint_size).sum(),
+        live_keys: files.iter().map(|file| file.live_keys).sum(),
+        dead_keys: files.iter().map(|file| file.dead_keys).sum(),
+        dead_bytes: files.iter().map(|file| file.dead_bytes).sum(),
+        files,
+    })
+}
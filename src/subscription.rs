@@ -0,0 +1,108 @@
+use bytes::Bytes;
+use tokio::sync::broadcast::{self, error::RecvError, error::TryRecvError};
+
+use crate::Error;
+
+/// A write seen by a [`Subscription`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Put {
+        key: Bytes,
+        value: Bytes,
+        tstamp: i64,
+    },
+    Delete {
+        key: Bytes,
+        tstamp: i64,
+    },
+}
+
+impl Event {
+    pub fn key(&self) -> &Bytes {
+        match self {
+            Event::Put { key, .. } | Event::Delete { key, .. } => key,
+        }
+    }
+}
+
+/// Receives the writes to keys that start with a prefix, in the order they
+/// were made, returned by [`Handle::subscribe`](crate::Handle::subscribe).
+///
+/// An event is sent once its write is appended and visible to readers,
+/// which can be before it is synced to disk. Events are buffered up to the
+/// capacity set with
+/// [`Options::subscription_capacity`](crate::Options::subscription_capacity);
+/// a subscriber that falls further behind gets [`Error::Lagged`] with the
+/// number of writes it missed, to any key, then continues with the oldest one
+/// still buffered. Once the store is closed, buffered events are delivered and
+/// then [`Error::Closed`] is returned.
+#[derive(Debug)]
+pub struct Subscription {
+    /// Id of the keyspace whose writes are reported.
+    keyspace: u32,
+    /// Prefix of the keys to report.
+    prefix: Bytes,
+    events: broadcast::Receiver<(u32, Event)>,
+}
+
+impl Subscription {
+    pub(super) fn new(
+        keyspace: u32,
+        prefix: Bytes,
+        events: broadcast::Receiver<(u32, Event)>,
+    ) -> Self {
+        Self {
+            keyspace,
+            prefix,
+            events,
+        }
+    }
+
+    /// Waits for the next event.
+    pub async fn recv(&mut self) -> Result<Event, Error> {
+        loop {
+            let event = self.events.recv().await.map_err(recv_error)?;
+            if let Some(event) = self.accept(event) {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Blocks until the next event. Must not be called from async code.
+    pub fn blocking_recv(&mut self) -> Result<Event, Error> {
+        loop {
+            let event = self.events.blocking_recv().map_err(recv_error)?;
+            if let Some(event) = self.accept(event) {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Returns the next event if one is buffered.
+    pub fn try_recv(&mut self) -> Result<Option<Event>, Error> {
+        loop {
+            let event = match self.events.try_recv() {
+                Ok(event) => event,
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Closed) => return Err(Error::Closed),
+                Err(TryRecvError::Lagged(missed)) => return Err(Error::Lagged(missed)),
+            };
+            if let Some(event) = self.accept(event) {
+                return Ok(Some(event));
+            }
+        }
+    }
+
+    /// Returns `event`, a write to keyspace `keyspace`, if it is about a key
+    /// of this subscription.
+    fn accept(&self, (keyspace, event): (u32, Event)) -> Option<Event> {
+        (keyspace == self.keyspace && event.key().starts_with(&self.prefix)).then_some(event)
+    }
+}
+
+fn recv_error(e: RecvError) -> Error {
+    match e {
+        RecvError::Closed => Error::Closed,
+        RecvError::Lagged(missed) => Error::Lagged(missed),
+    }
+}
@@ -0,0 +1,41 @@
+use std::{
+    io,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use tokio::sync::broadcast;
+
+/// Shortest period a task runs at, as `tokio::time::interval` rejects zero.
+pub(super) const MIN_PERIOD: Duration = Duration::from_millis(1);
+
+/// Runs `f` every `period`, but no more often than every [`MIN_PERIOD`], on
+/// a dedicated thread until `shutdown` fires.
+pub(super) fn spawn_periodic<F>(
+    name: &str,
+    period: Duration,
+    mut shutdown: broadcast::Receiver<()>,
+    mut f: F,
+) -> io::Result<JoinHandle<()>>
+where
+    F: FnMut() + Send + 'static,
+{
+    thread::Builder::new()
+        .name(format!("bitcask-{name}"))
+        .spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .expect("Failed to build background task runtime");
+            runtime.block_on(async move {
+                let mut interval = tokio::time::interval(period.max(MIN_PERIOD));
+                interval.tick().await;
+                loop {
+                    tokio::select! {
+                        _ = shutdown.recv() => break,
+                        _ = interval.tick() => f(),
+                    }
+                }
+            })
+        })
+}
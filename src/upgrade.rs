@@ -0,0 +1,93 @@
+use std::{fs, io, path::Path};
+
+use bytes::Bytes;
+use serde::Deserialize;
+
+use crate::{
+    bufio::BufReaderWithPos,
+    lock::DirLock,
+    log::{self, LogWriter},
+    utils, DataFileEntry, Error, HintFileEntry,
+};
+
+/// A data file record as written before the format was versioned: plain
+/// bincode, without a frame or checksum.
+#[derive(Deserialize)]
+struct LegacyDataFileEntry {
+    tstamp: i64,
+    key: Bytes,
+    value: Option<Bytes>,
+}
+
+pub(super) fn upgrade<P: AsRef<Path>>(path: P) -> Result<(), Error> {
+    let _lock = DirLock::acquire(&path)?;
+    utils::remove_tmpfiles(&path)?;
+    let fileids: Vec<u64> = utils::sorted_fileids(&path)?.collect();
+    for (i, &fileid) in fileids.iter().enumerate() {
+        match log::check_header(utils::datafile_name(&path, fileid), fileid) {
+            Ok(()) => continue,
+            Err(Error::UnsupportedVersion { version: 0, .. }) => {}
+            Err(e) => return Err(e),
+        }
+        convert(&path, fileid, i + 1 == fileids.len())?;
+    }
+    Ok(())
+}
+
+/// Rewrites the legacy data file `fileid` in the current format, along with a
+/// hint file for it. Only the newest file may end in a record that a crash
+/// cut short, which is dropped.
+fn convert<P: AsRef<Path>>(path: P, fileid: u64, newest: bool) -> Result<(), Error> {
+    let datafile = utils::datafile_name(&path, fileid);
+    let file = log::open(&datafile)?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReaderWithPos::new(file)?;
+    let tmpfile = utils::tmpfile_name(&datafile);
+    let mut writer = LogWriter::new(log::create(&tmpfile)?)?;
+    let mut hints = Vec::new();
+    loop {
+        let pos = reader.pos();
+        if pos == file_len {
+            break;
+        }
+        let entry: LegacyDataFileEntry = match bincode::deserialize_from(&mut reader) {
+            Ok(entry) => entry,
+            Err(e) => match *e {
+                bincode::ErrorKind::Io(ref ioe)
+                    if ioe.kind() == io::ErrorKind::UnexpectedEof && newest =>
+                {
+                    eprintln!(
+                        "dropping {} bytes of incomplete records from the end of data file {fileid}",
+                        file_len - pos
+                    );
+                    break;
+                }
+                _ => return Err(Error::Corruption { fileid, pos }),
+            },
+        };
+        let tombstone = entry.value.is_none();
+        // Stores from before keyspaces only have the default one.
+        let index = writer.append(&DataFileEntry::new(
+            0,
+            entry.tstamp,
+            entry.key.clone(),
+            entry.value,
+        ))?;
+        hints.push(HintFileEntry {
+            tstamp: entry.tstamp,
+            keyspace: 0,
+            len: index.len,
+            pos: index.pos,
+            key: entry.key,
+            tombstone,
+            expires: None,
+        });
+    }
+    writer.sync()?;
+
+    // A hint file left next to the old data file would point into it.
+    let hintfile = utils::hintfile_name(&path, fileid);
+    utils::remove_if_exists(&hintfile)?;
+    fs::rename(tmpfile, datafile)?;
+    log::write_atomic(hintfile, &hints)
+}
@@ -9,6 +9,14 @@ const DATAFILE_EXT: &str = "data";
 
 const HINTFILE_EXT: &str = "hint";
 
+const TMPFILE_EXT: &str = "tmp";
+
+const LOCKFILE_NAME: &str = "LOCK";
+
+const MANIFEST_NAME: &str = "MANIFEST";
+
+const KEYSPACES_NAME: &str = "KEYSPACES";
+
 pub(super) fn datafile_name<P: AsRef<Path>>(path: P, fileid: u64) -> PathBuf {
     path.as_ref()
         .join(format!("{fileid}.bitcask.{DATAFILE_EXT}"))
@@ -19,6 +27,50 @@ pub(super) fn hintfile_name<P: AsRef<Path>>(path: P, fileid: u64) -> PathBuf {
         .join(format!("{fileid}.bitcask.{HINTFILE_EXT}"))
 }
 
+pub(super) fn lockfile_name<P: AsRef<Path>>(path: P) -> PathBuf {
+    path.as_ref().join(LOCKFILE_NAME)
+}
+
+pub(super) fn manifest_name<P: AsRef<Path>>(path: P) -> PathBuf {
+    path.as_ref().join(MANIFEST_NAME)
+}
+
+pub(super) fn keyspaces_name<P: AsRef<Path>>(path: P) -> PathBuf {
+    path.as_ref().join(KEYSPACES_NAME)
+}
+
+pub(super) fn tmpfile_name<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut name = path.as_ref().as_os_str().to_owned();
+    name.push(format!(".{TMPFILE_EXT}"));
+    PathBuf::from(name)
+}
+
+pub(super) fn remove_tmpfiles<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    for entry in fs::read_dir(&path)? {
+        let p = entry?.path();
+        if p.is_file() && p.extension() == Some(OsStr::new(TMPFILE_EXT)) {
+            fs::remove_file(p)?;
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn remove_if_exists<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Size of the file at `path`, `None` if it does not exist.
+pub(super) fn file_size<P: AsRef<Path>>(path: P) -> io::Result<Option<u64>> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(Some(metadata.len())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 pub(super) fn sorted_fileids<P: AsRef<Path>>(path: P) -> io::Result<impl Iterator<Item = u64>> {
     Ok(fs::read_dir(&path)?
         .filter_map(std::result::Result::ok)
@@ -35,6 +87,11 @@ pub(super) fn sorted_fileids<P: AsRef<Path>>(path: P) -> io::Result<impl Iterato
         .into_iter())
 }
 
+/// Whether a value with the deadline `expires` reads as deleted at `now`.
+pub(super) fn is_expired(expires: Option<i64>, now: i64) -> bool {
+    expires.is_some_and(|expires| expires <= now)
+}
+
 pub(super) fn timestamp() -> i64 {
     chrono::Local::now()
         .timestamp_nanos_opt()
@@ -1,136 +1,467 @@
-use std::{cell::RefCell, collections::HashMap, fs, sync::Arc};
+use std::{cell::RefCell, fs, io, ops::Range, sync::Arc, time::Duration};
 
 use bytes::Bytes;
 
 use crate::{
-    context::{Context, KeyDirEntry},
-    log::{self, LogDir, LogStatistics, LogWriter},
-    utils, DataFileEntry, Error,
+    batch::WriteBatch,
+    context::{Context, KeyDirEntry, Keydir},
+    lock::DirLock,
+    log::{self, KeyspaceStatistics, LogDir, LogIndex, LogWriter},
+    merge::MergedEntry,
+    subscription::Event,
+    utils, BatchMarker, DataFileEntry, Error, HintFileEntry, SyncPolicy,
 };
 
-const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
-
 #[allow(dead_code)]
 #[derive(Debug)]
 pub(super) struct Writer {
     ctx: Arc<Context>,
     readers: RefCell<LogDir>,
     writer: LogWriter,
-    stats: HashMap<u64, LogStatistics>,
+    stats: KeyspaceStatistics,
     active_fileid: u64,
     written_bytes: u64,
+    hints: Vec<HintFileEntry>,
+    sync_policy: SyncPolicy,
+    /// Length of the active data file that is known to be on disk.
+    synced_bytes: u64,
+    seq: u64,
+    // Dropped after the active data file is sealed in `drop`.
+    _lock: DirLock,
 }
 
 impl Writer {
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn new(
         ctx: Arc<Context>,
         readers: RefCell<LogDir>,
         writer: LogWriter,
-        stats: HashMap<u64, LogStatistics>,
+        stats: KeyspaceStatistics,
         active_fileid: u64,
-        written_bytes: u64,
+        sync_policy: SyncPolicy,
+        lock: DirLock,
     ) -> Self {
         Self {
             ctx,
             readers,
+            written_bytes: writer.len(),
             writer,
             stats,
             active_fileid,
-            written_bytes,
+            hints: Vec::new(),
+            sync_policy,
+            synced_bytes: 0,
+            seq: 0,
+            _lock: lock,
         }
     }
 
-    pub(super) fn put(&mut self, key: Bytes, value: Bytes) -> Result<(), Error> {
-        let keydir_entry = self.write(utils::timestamp(), key.clone(), Some(value))?;
+    pub(super) fn put(&mut self, keydir: &Keydir, key: Bytes, value: Bytes) -> Result<(), Error> {
+        let tstamp = utils::timestamp();
+        let datafile_entry =
+            DataFileEntry::new(keydir.id(), tstamp, key.clone(), Some(value.clone()));
+        let keydir_entry = self.write(keydir, datafile_entry)?;
+        self.keydir_set(keydir, key.clone(), keydir_entry);
+        self.ctx
+            .publish(keydir.id(), || Event::Put { key, value, tstamp });
+        Ok(())
+    }
 
-        if let Some(prev_entry) = self.ctx.keydir_set(key, keydir_entry) {
-            self.stats
-                .entry(prev_entry.value().fileid)
-                .or_default()
-                .overwrite(prev_entry.value().len);
+    /// Writes a value that reads as deleted once `ttl` has passed.
+    pub(super) fn put_with_ttl(
+        &mut self,
+        keydir: &Keydir,
+        key: Bytes,
+        value: Bytes,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let tstamp = utils::timestamp();
+        let expires = i64::try_from(ttl.as_nanos())
+            .ok()
+            .and_then(|ttl| tstamp.checked_add(ttl));
+        let datafile_entry = DataFileEntry {
+            expires,
+            ..DataFileEntry::new(keydir.id(), tstamp, key.clone(), Some(value.clone()))
+        };
+        let keydir_entry = self.write(keydir, datafile_entry)?;
+        self.keydir_set(keydir, key.clone(), keydir_entry);
+        self.ctx
+            .publish(keydir.id(), || Event::Put { key, value, tstamp });
+        Ok(())
+    }
+
+    pub(super) fn delete(&mut self, keydir: &Keydir, key: Bytes) -> Result<bool, Error> {
+        let tstamp = utils::timestamp();
+        self.write(
+            keydir,
+            DataFileEntry::new(keydir.id(), tstamp, key.clone(), None),
+        )?;
+        let deleted = self.keydir_remove(keydir, &key);
+        self.ctx
+            .publish(keydir.id(), || Event::Delete { key, tstamp });
+        Ok(deleted)
+    }
+
+    /// Writes `new` as the value of `key`, `None` deleting it, provided the
+    /// current value is `expected`. Returns whether it did.
+    pub(super) fn compare_and_swap(
+        &mut self,
+        keydir: &Keydir,
+        key: Bytes,
+        expected: Option<Bytes>,
+        new: Option<Bytes>,
+    ) -> Result<bool, Error> {
+        if keydir.is_dropped() {
+            return Err(Error::KeyspaceDropped);
         }
+        if self.current(keydir, &key)? != expected {
+            return Ok(false);
+        }
+        match new {
+            Some(value) => self.put(keydir, key, value)?,
+            None if expected.is_some() => {
+                self.delete(keydir, key)?;
+            }
+            None => {}
+        }
+        Ok(true)
+    }
 
-        Ok(())
+    /// Reads the value `key` currently maps to. Merges only remove files
+    /// once the keydir no longer points into them, which takes the writer
+    /// lock, so the file read here cannot go away underneath.
+    fn current(&self, keydir: &Keydir, key: &Bytes) -> Result<Option<Bytes>, Error> {
+        let Some(entry) = keydir.entries().get(key) else {
+            return Ok(None);
+        };
+        let keydir_entry = entry.value();
+        if utils::is_expired(keydir_entry.expires, utils::timestamp()) {
+            return Ok(None);
+        }
+        let datafile_entry = unsafe {
+            self.readers.borrow_mut().read::<DataFileEntry, _>(
+                &self.ctx.path,
+                keydir_entry.fileid,
+                keydir_entry.len,
+                keydir_entry.pos,
+            )?
+        };
+        Ok(datafile_entry.value)
     }
 
-    pub(super) fn delete(&mut self, key: Bytes) -> Result<bool, Error> {
-        self.write(utils::timestamp(), key.clone(), None)?;
+    /// Marks keyspace `id`, which was just removed from the registry, as
+    /// dropped, counting all of its values as dead so that compaction
+    /// reclaims them. Takes time in the number of data files, not keys.
+    pub(super) fn drop_keyspace(&mut self, id: u32) {
+        self.ctx.keydirs().drop_keyspace(id);
+        self.stats.drop_keyspace(id);
+    }
 
-        match self.ctx.get_keydir().remove(&key) {
-            Some(prev_entry) => {
-                self.stats
-                    .entry(prev_entry.value().fileid)
-                    .or_default()
-                    .overwrite(prev_entry.value().len);
-                Ok(true)
+    /// Appends the operations of `batch` between a begin and a commit marker,
+    /// then applies all of them to the keydir in one step.
+    pub(super) fn write_batch(&mut self, keydir: &Keydir, batch: WriteBatch) -> Result<(), Error> {
+        if keydir.is_dropped() {
+            return Err(Error::KeyspaceDropped);
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let keyspace = keydir.id();
+        let tstamp = utils::timestamp();
+        let mut datafile_entries = Vec::with_capacity(batch.len() + 2);
+        datafile_entries.push(DataFileEntry::begin(keyspace, tstamp, batch.len() as u64));
+        datafile_entries.extend(
+            batch
+                .ops
+                .into_iter()
+                .map(|(key, value)| DataFileEntry::new(keyspace, tstamp, key, value)),
+        );
+        datafile_entries.push(DataFileEntry::marker(keyspace, tstamp, BatchMarker::Commit));
+        let indexes = self.writer.append_all(&datafile_entries)?;
+
+        let mut updates = Vec::with_capacity(datafile_entries.len() - 2);
+        let mut events = Vec::with_capacity(datafile_entries.len() - 2);
+        for (datafile_entry, index) in datafile_entries.into_iter().zip(indexes) {
+            let keydir_entry = self.record(&datafile_entry, index);
+            if datafile_entry.batch.is_none() {
+                events.push((datafile_entry.key.clone(), datafile_entry.value.clone()));
+                updates.push((
+                    datafile_entry.key,
+                    datafile_entry.value.map(|_| keydir_entry),
+                ));
             }
-            None => Ok(false),
         }
+        keydir.apply_batch(|| {
+            for (key, keydir_entry) in updates {
+                match keydir_entry {
+                    Some(keydir_entry) => self.keydir_set(keydir, key, keydir_entry),
+                    None => {
+                        self.keydir_remove(keydir, &key);
+                    }
+                }
+            }
+        });
+        for (key, value) in events {
+            self.ctx.publish(keyspace, || match value {
+                Some(value) => Event::Put { key, value, tstamp },
+                None => Event::Delete { key, tstamp },
+            });
+        }
+
+        self.finish_write()
     }
 
     fn write(
         &mut self,
-        tstamp: i64,
-        key: Bytes,
-        value: Option<Bytes>,
+        keydir: &Keydir,
+        datafile_entry: DataFileEntry,
     ) -> Result<KeyDirEntry, Error> {
-        let datafile_entry = DataFileEntry { tstamp, key, value };
+        if keydir.is_dropped() {
+            return Err(Error::KeyspaceDropped);
+        }
         let index = self.writer.append(&datafile_entry)?;
-        //self.writer.sync()?;
+        let keydir_entry = self.record(&datafile_entry, index);
+        self.finish_write()?;
+        Ok(keydir_entry)
+    }
+
+    /// Accounts for a record appended to the active data file and returns
+    /// where it was written.
+    fn record(&mut self, datafile_entry: &DataFileEntry, index: LogIndex) -> KeyDirEntry {
         self.written_bytes += index.len;
+        self.ctx.metrics.appended(index.len);
+        self.ctx.metrics.unsynced(self.unsynced_bytes());
+
+        // Batch markers never hold a key, so they are left out of hint files.
+        if datafile_entry.batch.is_none() {
+            self.hints.push(HintFileEntry {
+                tstamp: datafile_entry.tstamp,
+                keyspace: datafile_entry.keyspace,
+                len: index.len,
+                pos: index.pos,
+                key: datafile_entry.key.clone(),
+                tombstone: datafile_entry.value.is_none(),
+                expires: datafile_entry.expires,
+            });
+        }
 
         {
-            let entry = self.stats.entry(self.active_fileid).or_default();
+            let entry = self.stats.file(datafile_entry.keyspace, self.active_fileid);
             if datafile_entry.value.is_some() {
-                entry.add_live();
+                entry.add_live(index.len);
             } else {
                 entry.add_dead(index.len);
             }
         }
 
-        let keydir_entry = KeyDirEntry {
+        KeyDirEntry {
             fileid: self.active_fileid,
             len: index.len,
             pos: index.pos,
-            tstamp,
-        };
+            tstamp: datafile_entry.tstamp,
+            expires: datafile_entry.expires,
+        }
+    }
+
+    /// Completes an append of one record or batch: syncs if the sync policy
+    /// asks for it and starts a new data file once the active one is full.
+    fn finish_write(&mut self) -> Result<(), Error> {
+        self.seq += 1;
+        // `SyncPolicy::Always` is handled by the caller through group commit,
+        // once the writer lock is released.
+        if let SyncPolicy::Bytes(nbytes) = self.sync_policy {
+            if self.unsynced_bytes() >= nbytes {
+                self.sync()?;
+            }
+        }
 
-        if self.written_bytes > MAX_FILE_SIZE {
+        if self.written_bytes > self.ctx.max_file_size {
             self.new_active_datafile(self.active_fileid + 1)?;
         }
+        Ok(())
+    }
 
-        Ok(keydir_entry)
+    fn keydir_set(&mut self, keydir: &Keydir, key: Bytes, keydir_entry: KeyDirEntry) {
+        if let Some(prev_entry) = keydir.insert(key, keydir_entry) {
+            self.stats
+                .file(keydir.id(), prev_entry.value().fileid)
+                .overwrite(prev_entry.value().len);
+        }
+    }
+
+    fn keydir_remove(&mut self, keydir: &Keydir, key: &Bytes) -> bool {
+        match keydir.remove(key) {
+            Some(prev_entry) => {
+                self.stats
+                    .file(keydir.id(), prev_entry.value().fileid)
+                    .overwrite(prev_entry.value().len);
+                true
+            }
+            None => false,
+        }
     }
 
     pub(super) fn sync(&mut self) -> Result<(), Error> {
+        if self.unsynced_bytes() == 0 {
+            return Ok(());
+        }
         self.writer.sync()?;
+        self.mark_synced(self.active_fileid, self.written_bytes);
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub(super) fn get_stats(&self) -> &HashMap<u64, LogStatistics> {
+    fn unsynced_bytes(&self) -> u64 {
+        self.written_bytes - self.synced_bytes
+    }
+
+    /// Records a sync that put the first `len` bytes of data file `fileid`
+    /// on disk.
+    pub(super) fn mark_synced(&mut self, fileid: u64, len: u64) {
+        self.ctx.metrics.synced();
+        if fileid == self.active_fileid && len > self.synced_bytes {
+            self.synced_bytes = len;
+            self.ctx.metrics.unsynced(self.unsynced_bytes());
+        }
+    }
+
+    /// Sequence number of the last append.
+    pub(super) fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Returns the last sequence number together with what to sync to make
+    /// every append up to it durable. That is the active data file alone,
+    /// since sealed files are synced when they are rotated.
+    pub(super) fn sync_target(&self) -> io::Result<(u64, SyncTarget)> {
+        let target = SyncTarget {
+            fileid: self.active_fileid,
+            len: self.written_bytes,
+            file: self.writer.try_clone_file()?,
+        };
+        Ok((self.seq, target))
+    }
+
+    pub(super) fn active_fileid(&self) -> u64 {
+        self.active_fileid
+    }
+
+    pub(super) fn written_bytes(&self) -> u64 {
+        self.written_bytes
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.writer.is_empty()
+    }
+
+    /// Seals the active data file unless it is empty, so that every write so
+    /// far is in a sealed file, and returns the id of the active file.
+    pub(super) fn rotate(&mut self) -> Result<u64, Error> {
+        if !self.is_empty() {
+            self.new_active_datafile(self.active_fileid + 1)?;
+        }
+        Ok(self.active_fileid)
+    }
+
+    /// Seals the active data file and moves the writer past `count` unused
+    /// file ids. Merge output written under those ids sorts after every file
+    /// being merged, but before anything written while the merge runs.
+    pub(super) fn reserve_fileids(&mut self, count: u64) -> Result<Range<u64>, Error> {
+        let start = self.active_fileid + 1;
+        self.new_active_datafile(start + count)?;
+        Ok(start..start + count)
+    }
+
+    /// Points the keydirs at the merged copies in `fileid`, skipping keys
+    /// that were overwritten or deleted, or whose keyspace was dropped, since
+    /// they were copied. `tombstones` holds the keyspace and length of the
+    /// tombstones carried over into the file.
+    pub(super) fn commit_merge(
+        &mut self,
+        fileid: u64,
+        entries: Vec<MergedEntry>,
+        tombstones: Vec<(u32, u64)>,
+    ) {
+        for (keyspace, len) in tombstones {
+            self.stats.file(keyspace, fileid).add_dead(len);
+        }
+        for merged in entries {
+            let keydir = self.ctx.keydirs().get(merged.keyspace);
+            let live = keydir
+                .entries()
+                .get(&merged.key)
+                .is_some_and(|e| e.value().fileid == merged.fileid && e.value().pos == merged.pos);
+            let stats = self.stats.file(merged.keyspace, fileid);
+            if live {
+                stats.add_live(merged.keydir_entry.len);
+                keydir.insert(merged.key, merged.keydir_entry);
+            } else {
+                stats.add_dead(merged.keydir_entry.len);
+            }
+        }
+    }
+
+    pub(super) fn finish_merge(&mut self, fileids: &[u64]) {
+        for &fileid in fileids {
+            self.stats.remove(fileid);
+        }
+        self.readers.borrow_mut().clear();
+    }
+
+    pub(super) fn get_stats(&self) -> &KeyspaceStatistics {
         &self.stats
     }
 
+    pub(super) fn get_stats_mut(&mut self) -> &mut KeyspaceStatistics {
+        &mut self.stats
+    }
+
+    /// Syncs the active data file and writes its hint file.
+    fn seal(&mut self) -> Result<(), Error> {
+        self.writer.sync()?;
+        self.mark_synced(self.active_fileid, self.written_bytes);
+        log::write_atomic(
+            utils::hintfile_name(&self.ctx.path, self.active_fileid),
+            &std::mem::take(&mut self.hints),
+        )
+    }
+
     fn new_active_datafile(&mut self, fileid: u64) -> Result<(), Error> {
+        if self.is_empty() {
+            utils::remove_if_exists(utils::datafile_name(&self.ctx.path, self.active_fileid))?;
+        } else {
+            self.seal()?;
+        }
         self.active_fileid = fileid;
         self.writer = LogWriter::new(log::create(utils::datafile_name(
             self.ctx.path.as_path(),
             self.active_fileid,
         ))?)?;
-        self.written_bytes = 0;
+        self.written_bytes = self.writer.len();
+        self.synced_bytes = 0;
+        self.ctx.metrics.rotation();
         Ok(())
     }
 }
 
+/// The active data file as of some append, to be synced without holding the
+/// writer lock.
+pub(super) struct SyncTarget {
+    pub(super) fileid: u64,
+    pub(super) len: u64,
+    pub(super) file: fs::File,
+}
+
 impl Drop for Writer {
     fn drop(&mut self) {
-        if self.written_bytes != 0 {
+        if !self.is_empty() {
+            if let Err(e) = self.seal() {
+                eprintln!("failed to seal data file - {e}");
+            }
             return;
         }
         let active_datafile = utils::datafile_name(&self.ctx.path, self.active_fileid);
         if let Err(e) = fs::remove_file(active_datafile) {
-            println!("{e}aaa");
+            eprintln!("failed to remove empty data file - {e}");
         }
     }
 }
@@ -0,0 +1,36 @@
+use bitcask::{AsyncKeyValueStorage, Bitcask, SyncPolicy};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, value};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn async_handle_reads_and_writes_from_many_tasks() {
+    let tmpdir = TempDir::new().unwrap();
+    let options = common::options().sync_policy(SyncPolicy::Always).readers(1);
+    let bitcask = Bitcask::open_with(tmpdir.path(), options).unwrap();
+    let handle = bitcask.get_async_handle();
+
+    let tasks: Vec<_> = (0..32)
+        .map(|i| {
+            let handle = handle.clone();
+            tokio::spawn(async move {
+                handle.set(key(i), value(i)).await.unwrap();
+                assert_eq!(handle.get(key(i)).await.unwrap(), Some(value(i)));
+                assert!(!handle.put_if_absent(key(i), value(0)).await.unwrap());
+                if i % 2 == 0 {
+                    assert!(handle.del(key(i)).await.unwrap());
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    for i in 0..32 {
+        let expected = (i % 2 == 1).then(|| value(i));
+        assert_eq!(handle.get(key(i)).await.unwrap(), expected);
+    }
+}
@@ -0,0 +1,75 @@
+use bitcask::{Bitcask, Error, KeyValueStorage};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, open_small, value};
+
+#[test]
+fn checkpoint_is_an_openable_copy() {
+    let tmpdir = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let dest = dest.path().join("checkpoint");
+
+    let bitcask = open_small(tmpdir.path());
+    let handle = bitcask.get_handle();
+    for i in 0..10 {
+        handle.set(key(i), value(i)).unwrap();
+    }
+    handle.del(key(3)).unwrap();
+    handle.checkpoint(&dest).unwrap();
+    assert!(dest.join("MANIFEST").exists());
+    assert!(matches!(
+        handle.checkpoint(&dest),
+        Err(Error::AlreadyExists)
+    ));
+
+    handle.set(key(10), value(10)).unwrap();
+    handle.set(key(0), value(10)).unwrap();
+    handle.merge().unwrap();
+
+    let checkpoint = open_small(&dest);
+    let checkpoint = checkpoint.get_handle();
+    for i in 0..10 {
+        let expected = (i != 3).then(|| value(i));
+        assert_eq!(checkpoint.get(key(i)).unwrap(), expected);
+    }
+    assert_eq!(checkpoint.get(key(10)).unwrap(), None);
+    assert_eq!(handle.get(key(0)).unwrap(), Some(value(10)));
+}
+
+#[test]
+fn restore_reassembles_incremental_checkpoints() {
+    let tmpdir = TempDir::new().unwrap();
+    let backups = TempDir::new().unwrap();
+    let full = backups.path().join("full");
+    let incremental = backups.path().join("incremental");
+    let restored = backups.path().join("restored");
+
+    let bitcask = open_small(tmpdir.path());
+    let handle = bitcask.get_handle();
+    for i in 0..10 {
+        handle.set(key(i), value(i)).unwrap();
+    }
+    handle.checkpoint(&full).unwrap();
+
+    handle.del(key(0)).unwrap();
+    handle.set(key(1), value(11)).unwrap();
+    handle.merge().unwrap();
+    handle.set(key(10), value(10)).unwrap();
+    handle.checkpoint_incremental(&incremental, &full).unwrap();
+    // Everything in the full checkpoint is older than the merge output.
+    for entry in std::fs::read_dir(&full).unwrap() {
+        let name = entry.unwrap().file_name();
+        assert!(name == "MANIFEST" || !incremental.join(name).exists());
+    }
+
+    Bitcask::restore(&[&full, &incremental], &restored).unwrap();
+    let restored = open_small(&restored);
+    let restored = restored.get_handle();
+    assert_eq!(restored.get(key(0)).unwrap(), None);
+    assert_eq!(restored.get(key(1)).unwrap(), Some(value(11)));
+    for i in 2..=10 {
+        assert_eq!(restored.get(key(i)).unwrap(), Some(value(i)));
+    }
+}
@@ -0,0 +1,139 @@
+use std::fs;
+
+use bitcask::{ChangeCursor, Event, KeyValueStorage, WriteBatch};
+use bytes::Bytes;
+use tempfile::TempDir;
+
+mod common;
+
+use common::open;
+
+fn batch() -> WriteBatch {
+    let mut batch = WriteBatch::new();
+    batch.put(Bytes::from("a"), Bytes::from("1"));
+    batch.put(Bytes::from("b"), Bytes::from("2"));
+    batch.delete(Bytes::from("c"));
+    batch
+}
+
+#[test]
+fn write_batch_applies_every_operation() {
+    let tmpdir = TempDir::new().unwrap();
+    {
+        let bitcask = open(&tmpdir);
+        let handle = bitcask.get_handle();
+        handle.set(Bytes::from("c"), Bytes::from("3")).unwrap();
+        handle.write_batch(batch()).unwrap();
+        assert_eq!(
+            handle.get(Bytes::from("a")).unwrap(),
+            Some(Bytes::from("1"))
+        );
+        assert_eq!(handle.get(Bytes::from("c")).unwrap(), None);
+    }
+
+    let bitcask = open(&tmpdir);
+    let handle = bitcask.get_handle();
+    assert_eq!(
+        handle.get(Bytes::from("a")).unwrap(),
+        Some(Bytes::from("1"))
+    );
+    assert_eq!(
+        handle.get(Bytes::from("b")).unwrap(),
+        Some(Bytes::from("2"))
+    );
+    assert_eq!(handle.get(Bytes::from("c")).unwrap(), None);
+}
+
+#[test]
+fn open_drops_batch_without_commit_marker() {
+    let (datafile, before_batch) = {
+        let tmpdir = TempDir::new().unwrap();
+        let bitcask = open(&tmpdir);
+        let handle = bitcask.get_handle();
+        handle.set(Bytes::from("c"), Bytes::from("3")).unwrap();
+        let path = tmpdir.path().join("0.bitcask.data");
+        let before_batch = fs::metadata(&path).unwrap().len() as usize;
+        handle.write_batch(batch()).unwrap();
+        (fs::read(&path).unwrap(), before_batch)
+    };
+
+    for len in before_batch..=datafile.len() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("0.bitcask.data");
+        fs::write(&path, &datafile[..len]).unwrap();
+
+        let committed = len == datafile.len();
+        let bitcask = open(&tmpdir);
+        let handle = bitcask.get_handle();
+        assert_eq!(
+            handle.get(Bytes::from("a")).unwrap(),
+            committed.then(|| Bytes::from("1")),
+            "prefix of {len} bytes"
+        );
+        assert_eq!(
+            handle.get(Bytes::from("c")).unwrap(),
+            (!committed).then(|| Bytes::from("3")),
+            "prefix of {len} bytes"
+        );
+        let expected_len = if committed {
+            datafile.len()
+        } else {
+            before_batch
+        };
+        assert_eq!(fs::metadata(&path).unwrap().len(), expected_len as u64);
+    }
+}
+
+/// Offset of the last record that ends at `end` in `datafile`.
+fn last_record(datafile: &[u8], start: usize, end: usize) -> usize {
+    let mut pos = start;
+    loop {
+        let len = u64::from_le_bytes(datafile[pos + 4..pos + 12].try_into().unwrap());
+        let next = pos + 12 + len as usize;
+        if next == end {
+            return pos;
+        }
+        pos = next;
+    }
+}
+
+#[test]
+fn open_keeps_writes_after_batch_without_commit_marker() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("0.bitcask.data");
+    let datafile = {
+        let bitcask = open(&tmpdir);
+        let handle = bitcask.get_handle();
+        handle.set(Bytes::from("c"), Bytes::from("3")).unwrap();
+        let before_batch = fs::metadata(&path).unwrap().len() as usize;
+        handle.write_batch(batch()).unwrap();
+        let after_batch = fs::metadata(&path).unwrap().len() as usize;
+        handle.set(Bytes::from("single"), Bytes::from("4")).unwrap();
+        let mut datafile = fs::read(&path).unwrap();
+        // As if the write of the batch failed before its commit marker.
+        datafile.drain(last_record(&datafile, before_batch, after_batch)..after_batch);
+        datafile
+    };
+    fs::write(&path, &datafile).unwrap();
+    fs::remove_file(tmpdir.path().join("0.bitcask.hint")).unwrap();
+
+    let bitcask = open(&tmpdir);
+    let handle = bitcask.get_handle();
+    assert_eq!(handle.get(Bytes::from("a")).unwrap(), None);
+    assert_eq!(
+        handle.get(Bytes::from("c")).unwrap(),
+        Some(Bytes::from("3"))
+    );
+    assert_eq!(
+        handle.get(Bytes::from("single")).unwrap(),
+        Some(Bytes::from("4"))
+    );
+    assert_eq!(fs::read(&path).unwrap(), datafile);
+
+    let mut changes = handle.changes(ChangeCursor::default()).unwrap();
+    let mut keys = Vec::new();
+    while let Some((event, _)) = changes.try_next().unwrap() {
+        keys.push(Event::key(&event).clone());
+    }
+    assert_eq!(keys, vec![Bytes::from("c"), Bytes::from("single")]);
+}
@@ -0,0 +1,64 @@
+use std::thread;
+
+use bitcask::KeyValueStorage;
+use bytes::Bytes;
+use tempfile::TempDir;
+
+mod common;
+
+use common::open;
+
+#[test]
+fn compare_and_swap_checks_current_value() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir);
+    let handle = bitcask.get_handle();
+    let key = Bytes::from("key");
+
+    assert!(handle.put_if_absent(key.clone(), Bytes::from("a")).unwrap());
+    assert!(!handle.put_if_absent(key.clone(), Bytes::from("b")).unwrap());
+    assert_eq!(handle.get(key.clone()).unwrap(), Some(Bytes::from("a")));
+
+    assert!(!handle
+        .compare_and_swap(key.clone(), Some(Bytes::from("b")), Some(Bytes::from("c")))
+        .unwrap());
+    assert!(handle
+        .compare_and_swap(key.clone(), Some(Bytes::from("a")), Some(Bytes::from("c")))
+        .unwrap());
+    assert_eq!(handle.get(key.clone()).unwrap(), Some(Bytes::from("c")));
+
+    assert!(!handle
+        .delete_if_equals(key.clone(), Bytes::from("a"))
+        .unwrap());
+    assert!(handle
+        .delete_if_equals(key.clone(), Bytes::from("c"))
+        .unwrap());
+    assert_eq!(handle.get(key.clone()).unwrap(), None);
+    assert!(handle.compare_and_swap(key.clone(), None, None).unwrap());
+}
+
+#[test]
+fn put_if_absent_has_one_winner() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir);
+    let handle = bitcask.get_handle();
+
+    let winners: usize = thread::scope(|s| {
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let handle = handle.clone();
+                s.spawn(move || {
+                    (0..100)
+                        .filter(|j| {
+                            handle
+                                .put_if_absent(Bytes::from(format!("id{j}")), Bytes::from(vec![i]))
+                                .unwrap()
+                        })
+                        .count()
+                })
+            })
+            .collect();
+        threads.into_iter().map(|t| t.join().unwrap()).sum()
+    });
+    assert_eq!(winners, 100);
+}
@@ -0,0 +1,139 @@
+use std::{thread, time::Duration};
+
+use bitcask::{Bitcask, ChangeCursor, Changes, Error, Event, KeyValueStorage, Options, WriteBatch};
+use bytes::Bytes;
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, open_small, value};
+
+/// Takes the events that were written so far, along with the cursor after
+/// the last one.
+fn drain(changes: &mut Changes) -> (Vec<Event>, ChangeCursor) {
+    let mut events = Vec::new();
+    let mut last = changes.cursor();
+    while let Some((event, cursor)) = changes.try_next().unwrap() {
+        assert!(cursor > last);
+        last = cursor;
+        events.push(event);
+    }
+    (events, last)
+}
+
+fn keys(events: &[Event]) -> Vec<Bytes> {
+    events.iter().map(Event::key).cloned().collect()
+}
+
+#[test]
+fn changes_follow_the_data_files_and_resume_from_a_cursor() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open_small(&tmpdir);
+    let handle = bitcask.get_handle();
+    for i in 0..10 {
+        handle.set(key(i), value(i)).unwrap();
+    }
+    handle.del(key(3)).unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put(key(10), value(10));
+    batch.delete(key(4));
+    handle.write_batch(batch).unwrap();
+
+    let mut changes = handle.changes(ChangeCursor::default()).unwrap();
+    let (events, cursor) = drain(&mut changes);
+    let expected: Vec<_> = (0..10).chain([3, 10, 4]).map(key).collect();
+    assert_eq!(keys(&events), expected);
+    assert_eq!(
+        events[0],
+        Event::Put {
+            key: key(0),
+            value: value(0),
+            tstamp: match events[0] {
+                Event::Put { tstamp, .. } => tstamp,
+                _ => unreachable!(),
+            },
+        }
+    );
+    assert!(matches!(events[10], Event::Delete { .. }));
+    assert!(matches!(events[12], Event::Delete { .. }));
+    assert!(cursor.fileid > 0);
+
+    drop((changes, handle, bitcask));
+    let bitcask = open_small(&tmpdir);
+    let handle = bitcask.get_handle();
+    handle.set(key(11), value(11)).unwrap();
+    let (events, _) = drain(&mut handle.changes(cursor).unwrap());
+    assert_eq!(keys(&events), vec![key(11)]);
+}
+
+#[test]
+fn changes_skip_merge_outputs_and_report_removed_writes() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open_small(&tmpdir);
+    let handle = bitcask.get_handle();
+    for i in 0..10 {
+        handle.set(key(i), value(i)).unwrap();
+        handle.set(key(i), value(i + 10)).unwrap();
+    }
+    let mut changes = handle.changes(ChangeCursor::default()).unwrap();
+    assert_eq!(drain(&mut changes).0.len(), 20);
+    let cursor = changes.cursor();
+
+    handle.merge().unwrap();
+    handle.set(key(20), value(20)).unwrap();
+    assert_eq!(keys(&drain(&mut changes).0), vec![key(20)]);
+    let (events, _) = drain(&mut handle.changes(cursor).unwrap());
+    assert_eq!(keys(&events), vec![key(20)]);
+
+    let mut stale = handle.changes(ChangeCursor::default()).unwrap();
+    assert!(matches!(stale.try_next(), Err(Error::CursorTooOld)));
+}
+
+#[test]
+fn changes_wait_for_new_writes() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open_small(&tmpdir);
+    let handle = bitcask.get_handle();
+    let cursor = handle.change_cursor().unwrap();
+    handle.set(key(0), value(0)).unwrap();
+
+    let reader = Bitcask::open_with(tmpdir.path(), Options::default().read_only(true)).unwrap();
+    let tailers = [
+        handle.changes(cursor).unwrap(),
+        reader
+            .get_handle()
+            .changes(cursor)
+            .unwrap()
+            .poll_interval(Duration::from_millis(10)),
+    ]
+    .map(|changes| {
+        thread::spawn(move || {
+            changes
+                .take(20)
+                .map(|change| change.unwrap().0.key().clone())
+                .collect::<Vec<_>>()
+        })
+    });
+    for i in 1..20 {
+        thread::sleep(Duration::from_millis(1));
+        handle.set(key(i), value(i)).unwrap();
+    }
+    for tailer in tailers {
+        assert_eq!(tailer.join().unwrap(), (0..20).map(key).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn changes_end_when_the_store_closes() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open_small(&tmpdir);
+    let handle = bitcask.get_handle();
+    handle.set(key(0), value(0)).unwrap();
+    let mut changes = handle.changes(handle.change_cursor().unwrap()).unwrap();
+    handle.set(key(1), value(1)).unwrap();
+    drop(bitcask);
+
+    assert_eq!(changes.next().unwrap().unwrap().0.key(), &key(1));
+    assert!(matches!(changes.next(), Some(Err(Error::Closed))));
+    assert!(changes.next().is_none());
+}
@@ -0,0 +1,39 @@
+#![allow(dead_code)]
+
+use std::path::Path;
+
+use bitcask::{Bitcask, CompactionOptions, Options};
+use bytes::Bytes;
+
+/// Options without background compaction, so that tests decide when to merge.
+pub fn options() -> Options {
+    Options::default().compaction(CompactionOptions::default().enabled(false))
+}
+
+pub fn open<P: AsRef<Path>>(path: P) -> Bitcask {
+    Bitcask::open_with(path, options()).unwrap()
+}
+
+/// Opens a store whose data files hold only a record or two, so that a few
+/// writes span several of them.
+pub fn open_small<P: AsRef<Path>>(path: P) -> Bitcask {
+    Bitcask::open_with(path, options().max_file_size(64)).unwrap()
+}
+
+pub fn key(i: usize) -> Bytes {
+    Bytes::from(format!("key{i:02}"))
+}
+
+pub fn value(i: usize) -> Bytes {
+    Bytes::from(format!("value{i:02}"))
+}
+
+/// Returns the value of the sample `name` in rendered metrics.
+pub fn sample(metrics: &str, name: &str) -> f64 {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no sample {name}"))
+        .parse()
+        .unwrap()
+}
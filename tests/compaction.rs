@@ -0,0 +1,101 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use bitcask::{Bitcask, CompactionOptions, Handle, KeyValueStorage};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, sample, value};
+
+fn open(tmpdir: &TempDir, compaction: CompactionOptions) -> Bitcask {
+    let options = common::options().max_file_size(64).compaction(compaction);
+    Bitcask::open_with(tmpdir.path(), options).unwrap()
+}
+
+fn merges(handle: &Handle) -> f64 {
+    sample(&handle.metrics().render(), "bitcask_merges_total")
+}
+
+/// Writes every key twice, leaving the sealed files of the first round
+/// holding nothing but dead entries.
+fn fragment(handle: &Handle) {
+    for round in 0..2 {
+        for i in 0..10 {
+            handle.set(key(i), value(round * 10 + i)).unwrap();
+        }
+    }
+}
+
+#[test]
+fn fragmented_sealed_files_are_merged_in_the_background() {
+    let tmpdir = TempDir::new().unwrap();
+    let compaction = CompactionOptions::default()
+        .interval(Duration::from_millis(10))
+        .min_fragmentation(0.5)
+        .min_dead_bytes(u64::MAX);
+    let bitcask = open(&tmpdir, compaction);
+    let handle = bitcask.get_handle();
+    fragment(&handle);
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while merges(&handle) == 0.0 {
+        assert!(Instant::now() < deadline, "nothing was merged");
+        thread::sleep(Duration::from_millis(10));
+    }
+    for i in 0..10 {
+        assert_eq!(handle.get(key(i)).unwrap(), Some(value(10 + i)));
+    }
+}
+
+#[test]
+fn dead_bytes_alone_are_enough_to_merge() {
+    let tmpdir = TempDir::new().unwrap();
+    let compaction = CompactionOptions::default()
+        .interval(Duration::ZERO)
+        .min_fragmentation(f64::INFINITY)
+        .min_dead_bytes(1);
+    let bitcask = open(&tmpdir, compaction);
+    let handle = bitcask.get_handle();
+    fragment(&handle);
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while merges(&handle) == 0.0 {
+        assert!(Instant::now() < deadline, "nothing was merged");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn files_below_both_thresholds_are_left_alone() {
+    let tmpdir = TempDir::new().unwrap();
+    let compaction = CompactionOptions::default()
+        .interval(Duration::from_millis(10))
+        .min_fragmentation(f64::INFINITY)
+        .min_dead_bytes(u64::MAX);
+    let bitcask = open(&tmpdir, compaction);
+    let handle = bitcask.get_handle();
+    fragment(&handle);
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(merges(&handle), 0.0);
+}
+
+#[test]
+fn dropping_the_store_stops_the_compaction_task() {
+    let tmpdir = TempDir::new().unwrap();
+    let compaction = CompactionOptions::default().interval(Duration::from_secs(3600));
+    let bitcask = open(&tmpdir, compaction.clone());
+    bitcask.get_handle().set(key(0), value(0)).unwrap();
+
+    // The task holds a handle of its own, and with it the writer and the
+    // directory lock, until its thread is joined. Drop must not wait for
+    // the next tick to get there.
+    let start = Instant::now();
+    drop(bitcask);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    let bitcask = open(&tmpdir, compaction);
+    assert_eq!(bitcask.get_handle().get(key(0)).unwrap(), Some(value(0)));
+}
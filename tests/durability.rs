@@ -0,0 +1,109 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use bitcask::{Bitcask, Handle, KeyValueStorage, SyncPolicy};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, sample, value};
+
+fn open(tmpdir: &TempDir, sync_policy: SyncPolicy) -> Bitcask {
+    let options = common::options().sync_policy(sync_policy);
+    Bitcask::open_with(tmpdir.path(), options).unwrap()
+}
+
+fn unsynced_bytes(handle: &Handle) -> f64 {
+    sample(&handle.metrics().render(), "bitcask_unsynced_bytes")
+}
+
+fn syncs(handle: &Handle) -> f64 {
+    sample(&handle.metrics().render(), "bitcask_syncs_total")
+}
+
+/// Waits up to a second for the background task to sync the active file.
+fn wait_for_sync(handle: &Handle) {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while unsynced_bytes(handle) > 0.0 {
+        assert!(Instant::now() < deadline, "writes were never synced");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn bytes_policy_syncs_once_enough_was_written() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir, SyncPolicy::Bytes(100));
+    let handle = bitcask.get_handle();
+
+    handle.set(key(0), value(0)).unwrap();
+    let unsynced = unsynced_bytes(&handle);
+    assert!(unsynced > 0.0 && unsynced < 100.0);
+    assert_eq!(syncs(&handle), 0.0);
+
+    let mut i = 1;
+    while syncs(&handle) == 0.0 {
+        handle.set(key(i), value(i)).unwrap();
+        i += 1;
+    }
+    assert_eq!(unsynced_bytes(&handle), 0.0);
+    assert!(i > 1);
+}
+
+#[test]
+fn interval_policy_syncs_in_the_background() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir, SyncPolicy::Interval(Duration::from_millis(10)));
+    let handle = bitcask.get_handle();
+    handle.set(key(0), value(0)).unwrap();
+    wait_for_sync(&handle);
+    assert!(syncs(&handle) >= 1.0);
+
+    // Nothing was written since, so there is nothing left to sync.
+    let before = syncs(&handle);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(syncs(&handle), before);
+}
+
+#[test]
+fn zero_interval_is_raised_instead_of_stopping_the_sync_task() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir, SyncPolicy::Interval(Duration::ZERO));
+    let handle = bitcask.get_handle();
+    for i in 0..3 {
+        handle.set(key(i), value(i)).unwrap();
+        wait_for_sync(&handle);
+    }
+}
+
+#[test]
+fn always_policy_shares_syncs_between_concurrent_writers() {
+    const THREADS: usize = 8;
+    const WRITES: usize = 50;
+
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir, SyncPolicy::Always);
+    let handle = bitcask.get_handle();
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let handle = &handle;
+            s.spawn(move || {
+                for i in 0..WRITES {
+                    handle.set(key(t * WRITES + i), value(i)).unwrap();
+                }
+            });
+        }
+    });
+
+    // Every write was acknowledged only once it was on disk, by a sync that
+    // usually covered other threads' writes as well.
+    assert_eq!(unsynced_bytes(&handle), 0.0);
+    let syncs = syncs(&handle);
+    assert!(syncs >= 1.0);
+    assert!(syncs < (THREADS * WRITES) as f64, "{syncs} syncs");
+    for i in 0..THREADS * WRITES {
+        assert_eq!(handle.get(key(i)).unwrap(), Some(value(i % WRITES)));
+    }
+}
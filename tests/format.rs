@@ -0,0 +1,161 @@
+use std::fs;
+
+use bitcask::{Bitcask, Error, KeyValueStorage};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, open, open_small, value};
+
+/// Writes a data file the way stores did before the format was versioned:
+/// bincode records of `(tstamp, key, value)` back to back.
+fn write_legacy(tmpdir: &TempDir, fileid: u64, records: &[(usize, Option<usize>)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (tstamp, &(k, v)) in records.iter().enumerate() {
+        let record = (tstamp as i64, key(k), v.map(value));
+        bincode::serialize_into(&mut data, &record).unwrap();
+    }
+    fs::write(datafile(tmpdir, fileid), &data).unwrap();
+    data
+}
+
+fn datafile(tmpdir: &TempDir, fileid: u64) -> std::path::PathBuf {
+    tmpdir.path().join(format!("{fileid}.bitcask.data"))
+}
+
+fn open_err(tmpdir: &TempDir) -> Error {
+    match Bitcask::open_with(tmpdir.path(), common::options()) {
+        Ok(_) => panic!("opened the store"),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn legacy_store_fails_to_open_until_upgraded() {
+    let tmpdir = TempDir::new().unwrap();
+    let sealed = write_legacy(
+        &tmpdir,
+        0,
+        &[(0, Some(0)), (1, Some(1)), (2, Some(2)), (1, None)],
+    );
+    let mut newest = write_legacy(&tmpdir, 1, &[(2, Some(12)), (3, Some(3))]);
+    // A crash cut the last record short.
+    newest.truncate(newest.len() - 3);
+    fs::write(datafile(&tmpdir, 1), &newest).unwrap();
+
+    assert!(matches!(
+        open_err(&tmpdir),
+        Error::UnsupportedVersion {
+            fileid: 0,
+            version: 0
+        }
+    ));
+    assert_eq!(fs::read(datafile(&tmpdir, 0)).unwrap(), sealed);
+
+    Bitcask::upgrade(tmpdir.path()).unwrap();
+    // Running it again changes nothing.
+    let upgraded = fs::read(datafile(&tmpdir, 0)).unwrap();
+    Bitcask::upgrade(tmpdir.path()).unwrap();
+    assert_eq!(fs::read(datafile(&tmpdir, 0)).unwrap(), upgraded);
+
+    let bitcask = open(&tmpdir);
+    let handle = bitcask.get_handle();
+    assert_eq!(handle.get(key(0)).unwrap(), Some(value(0)));
+    assert_eq!(handle.get(key(1)).unwrap(), None);
+    assert_eq!(handle.get(key(2)).unwrap(), Some(value(12)));
+    assert_eq!(handle.get(key(3)).unwrap(), None);
+    assert_eq!(handle.stats().unwrap().keys, 2);
+}
+
+#[test]
+fn legacy_store_with_corrupt_sealed_file_is_not_upgraded() {
+    let tmpdir = TempDir::new().unwrap();
+    let mut sealed = write_legacy(&tmpdir, 0, &[(0, Some(0)), (1, Some(1))]);
+    sealed.truncate(sealed.len() - 3);
+    fs::write(datafile(&tmpdir, 0), &sealed).unwrap();
+    write_legacy(&tmpdir, 1, &[(2, Some(2))]);
+
+    assert!(matches!(
+        Bitcask::upgrade(tmpdir.path()),
+        Err(Error::Corruption { fileid: 0, .. })
+    ));
+    assert_eq!(fs::read(datafile(&tmpdir, 0)).unwrap(), sealed);
+}
+
+#[test]
+fn unknown_version_fails_to_open() {
+    let tmpdir = TempDir::new().unwrap();
+    open(&tmpdir).get_handle().set(key(0), value(0)).unwrap();
+    let mut data = fs::read(datafile(&tmpdir, 0)).unwrap();
+    data[4] = 9;
+    fs::write(datafile(&tmpdir, 0), &data).unwrap();
+    fs::remove_file(tmpdir.path().join("0.bitcask.hint")).unwrap();
+
+    assert!(matches!(
+        open_err(&tmpdir),
+        Error::UnsupportedVersion {
+            fileid: 0,
+            version: 9
+        }
+    ));
+}
+
+/// Writes keys 0 to 9 to a store of small files and returns the first data
+/// file, which is sealed and holds key 0.
+fn sealed_datafile(tmpdir: &TempDir) -> Vec<u8> {
+    let bitcask = open_small(tmpdir);
+    let handle = bitcask.get_handle();
+    for i in 0..10 {
+        handle.set(key(i), value(i)).unwrap();
+    }
+    drop((handle, bitcask));
+    fs::read(datafile(tmpdir, 0)).unwrap()
+}
+
+/// Offset of the first record, past the file header.
+const FIRST_RECORD: usize = 8;
+
+#[test]
+fn corrupt_record_in_sealed_file_fails_reads_and_open() {
+    let tmpdir = TempDir::new().unwrap();
+    let mut data = sealed_datafile(&tmpdir);
+    // A byte of the payload of the first record, past its CRC and length.
+    data[FIRST_RECORD + 12 + 2] ^= 0x01;
+    fs::write(datafile(&tmpdir, 0), &data).unwrap();
+
+    // The hint file still lists the record, so open succeeds and only reading
+    // it notices.
+    {
+        let bitcask = open_small(&tmpdir);
+        let handle = bitcask.get_handle();
+        assert!(matches!(
+            handle.get(key(0)),
+            Err(Error::Corruption { fileid: 0, pos }) if pos == FIRST_RECORD as u64
+        ));
+        assert_eq!(handle.get(key(9)).unwrap(), Some(value(9)));
+    }
+
+    fs::remove_file(tmpdir.path().join("0.bitcask.hint")).unwrap();
+    assert!(matches!(
+        open_err(&tmpdir),
+        Error::Corruption { fileid: 0, pos } if pos == FIRST_RECORD as u64
+    ));
+    assert_eq!(fs::read(datafile(&tmpdir, 0)).unwrap(), data);
+}
+
+#[test]
+fn corrupt_length_in_sealed_file_fails_open() {
+    let tmpdir = TempDir::new().unwrap();
+    let mut data = sealed_datafile(&tmpdir);
+    // The highest byte of the length of the first record, which then reaches
+    // far past the end of the file.
+    data[FIRST_RECORD + 11] ^= 0x01;
+    fs::write(datafile(&tmpdir, 0), &data).unwrap();
+    fs::remove_file(tmpdir.path().join("0.bitcask.hint")).unwrap();
+
+    assert!(matches!(
+        open_err(&tmpdir),
+        Error::Corruption { fileid: 0, .. }
+    ));
+    assert_eq!(fs::read(datafile(&tmpdir, 0)).unwrap(), data);
+}
@@ -0,0 +1,76 @@
+use std::fs;
+
+use bitcask::KeyValueStorage;
+use bytes::Bytes;
+use tempfile::TempDir;
+
+mod common;
+
+use common::open;
+
+#[test]
+fn deleted_key_stays_deleted_after_hint_recovery() {
+    let tmpdir = TempDir::new().unwrap();
+
+    {
+        let bitcask = open(&tmpdir);
+        let handle = bitcask.get_handle();
+        handle
+            .set(Bytes::from("key"), Bytes::from("value"))
+            .unwrap();
+        handle
+            .set(Bytes::from("other"), Bytes::from("value"))
+            .unwrap();
+    }
+    {
+        let bitcask = open(&tmpdir);
+        let handle = bitcask.get_handle();
+        assert!(handle.del(Bytes::from("key")).unwrap());
+    }
+
+    // Both sealed data files must have been recovered from their hint files.
+    assert!(tmpdir.path().join("0.bitcask.hint").exists());
+    assert!(tmpdir.path().join("1.bitcask.hint").exists());
+
+    for _ in 0..2 {
+        let bitcask = open(&tmpdir);
+        let handle = bitcask.get_handle();
+        assert_eq!(handle.get(Bytes::from("key")).unwrap(), None);
+        assert_eq!(
+            handle.get(Bytes::from("other")).unwrap(),
+            Some(Bytes::from("value"))
+        );
+    }
+}
+
+#[test]
+fn open_replays_the_data_file_behind_a_corrupt_hint_file() {
+    let tmpdir = TempDir::new().unwrap();
+    {
+        let bitcask = open(&tmpdir);
+        let handle = bitcask.get_handle();
+        handle
+            .set(Bytes::from("key"), Bytes::from("value"))
+            .unwrap();
+        handle
+            .set(Bytes::from("other"), Bytes::from("value"))
+            .unwrap();
+        assert!(handle.del(Bytes::from("key")).unwrap());
+    }
+
+    let hintfile = tmpdir.path().join("0.bitcask.hint");
+    let mut hints = fs::read(&hintfile).unwrap();
+    let last = hints.len() - 1;
+    hints[last] ^= 0xff;
+    fs::write(&hintfile, &hints).unwrap();
+
+    let bitcask = open(&tmpdir);
+    let handle = bitcask.get_handle();
+    assert_eq!(handle.get(Bytes::from("key")).unwrap(), None);
+    assert_eq!(
+        handle.get(Bytes::from("other")).unwrap(),
+        Some(Bytes::from("value"))
+    );
+    let stats = handle.stats().unwrap();
+    assert_eq!((stats.live_keys, stats.dead_keys), (1, 2));
+}
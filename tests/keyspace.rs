@@ -0,0 +1,223 @@
+use bitcask::{Bitcask, Error, Event, KeyValueStorage, Options, WriteBatch};
+use bytes::Bytes;
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, value};
+
+fn open<P: AsRef<std::path::Path>>(path: P) -> Bitcask {
+    Bitcask::open_with(path, common::options().max_file_size(256)).unwrap()
+}
+
+fn kind(event: Event) -> (&'static str, Bytes) {
+    match event {
+        Event::Put { key, .. } => ("put", key),
+        Event::Delete { key, .. } => ("del", key),
+    }
+}
+
+#[test]
+fn keyspaces_keep_their_keys_apart() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(tmpdir.path());
+    let handle = bitcask.get_handle();
+    let users = bitcask.keyspace("users").unwrap();
+    let items = bitcask.keyspace("items").unwrap();
+    for i in 0..5 {
+        handle.set(key(i), value(i)).unwrap();
+        users.set(key(i), value(i + 10)).unwrap();
+    }
+    let mut batch = WriteBatch::new();
+    batch.put(key(0), value(20));
+    batch.delete(key(1));
+    items.write_batch(batch).unwrap();
+    users.del(key(4)).unwrap();
+
+    assert_eq!(handle.get(key(0)).unwrap(), Some(value(0)));
+    assert_eq!(users.get(key(0)).unwrap(), Some(value(10)));
+    assert_eq!(items.get(key(0)).unwrap(), Some(value(20)));
+    assert_eq!(handle.get(key(4)).unwrap(), Some(value(4)));
+    assert_eq!(users.get(key(4)).unwrap(), None);
+    assert_eq!(
+        users.range(..).map(Result::unwrap).collect::<Vec<_>>(),
+        (0..4).map(|i| (key(i), value(i + 10))).collect::<Vec<_>>()
+    );
+    assert_eq!(handle.keys(Bytes::new()).count(), 5);
+    assert_eq!(handle.range_rev(..).count(), 5);
+    assert_eq!(items.keys(Bytes::new()).collect::<Vec<_>>(), vec![key(0)]);
+    assert_eq!(
+        handle.snapshot().unwrap().range(..).count(),
+        5,
+        "the default keyspace holds none of the keys of the others"
+    );
+    assert_eq!(
+        users.snapshot().unwrap().get(key(1)).unwrap(),
+        Some(value(11))
+    );
+
+    // Keys of the default keyspace may be anything, and stay apart from the
+    // others no matter what they start with.
+    let odd = Bytes::from_static(b"\xff\xff\xff\xff\x00\x00\x00\x01key00");
+    handle.set(odd.clone(), value(9)).unwrap();
+    assert_eq!(handle.get(odd.clone()).unwrap(), Some(value(9)));
+    assert_eq!(users.get(odd.clone()).unwrap(), None);
+    assert_eq!(users.get(key(0)).unwrap(), Some(value(10)));
+    assert_eq!(handle.range(odd..).count(), 1);
+
+    let stats = handle.stats().unwrap();
+    assert_eq!((stats.keys, stats.live_keys, stats.dead_keys), (6, 6, 0));
+    let stats = users.stats().unwrap();
+    assert_eq!((stats.keys, stats.live_keys, stats.dead_keys), (4, 4, 2));
+    assert_eq!(items.stats().unwrap().keys, 1);
+    assert_eq!(bitcask.keyspaces(), vec!["users", "items"]);
+
+    drop((handle, users, items, bitcask));
+    let bitcask = open(tmpdir.path());
+    let users = bitcask.keyspace("users").unwrap();
+    assert_eq!(users.get(key(2)).unwrap(), Some(value(12)));
+    let stats = users.stats().unwrap();
+    assert_eq!((stats.keys, stats.live_keys, stats.dead_keys), (4, 4, 2));
+    assert_eq!(bitcask.get_handle().stats().unwrap().keys, 6);
+}
+
+#[test]
+fn dropped_keyspaces_are_empty_and_merged_away() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(tmpdir.path());
+    let handle = bitcask.get_handle();
+    let users = bitcask.keyspace("users").unwrap();
+    for i in 0..20 {
+        users.set(key(i), value(i)).unwrap();
+    }
+    users.del(key(0)).unwrap();
+    handle.set(key(0), value(0)).unwrap();
+
+    assert!(bitcask.drop_keyspace("users").unwrap());
+    assert!(!bitcask.drop_keyspace("users").unwrap());
+    assert!(bitcask.keyspaces().is_empty());
+    assert_eq!(users.get(key(1)).unwrap(), None);
+    assert_eq!(users.keys(Bytes::new()).count(), 0);
+    assert!(matches!(
+        users.set(key(1), value(1)),
+        Err(Error::KeyspaceDropped)
+    ));
+    // Every value the keyspace held is dead right away, without waiting for
+    // a merge.
+    let stats = users.stats().unwrap();
+    assert_eq!((stats.keys, stats.live_keys, stats.dead_keys), (0, 0, 21));
+    assert_eq!(handle.stats().unwrap().live_keys, 1);
+
+    drop(users);
+
+    // A keyspace of the same name starts out empty, also after a restart.
+    let users = bitcask.keyspace("users").unwrap();
+    assert_eq!(users.get(key(1)).unwrap(), None);
+    users.set(key(1), value(21)).unwrap();
+    drop((handle, users, bitcask));
+
+    let bitcask = open(tmpdir.path());
+    let handle = bitcask.get_handle();
+    let users = bitcask.keyspace("users").unwrap();
+    assert_eq!(users.keys(Bytes::new()).collect::<Vec<_>>(), vec![key(1)]);
+    assert_eq!(handle.get(key(0)).unwrap(), Some(value(0)));
+    let disk_size = handle.stats().unwrap().disk_size;
+
+    handle.merge().unwrap();
+    let stats = handle.stats().unwrap();
+    assert!(stats.disk_size < disk_size);
+    assert_eq!((stats.live_keys, stats.dead_keys), (1, 0));
+    let stats = users.stats().unwrap();
+    assert_eq!((stats.live_keys, stats.dead_keys), (1, 0));
+    drop((handle, users, bitcask));
+
+    let bitcask = open(tmpdir.path());
+    assert_eq!(
+        bitcask.keyspace("users").unwrap().get(key(1)).unwrap(),
+        Some(value(21))
+    );
+    assert_eq!(bitcask.get_handle().stats().unwrap().keys, 1);
+}
+
+#[test]
+fn subscriptions_and_changes_only_see_their_keyspace() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(tmpdir.path());
+    let handle = bitcask.get_handle();
+    let users = bitcask.keyspace("users").unwrap();
+    let mut all = handle.subscribe(Bytes::new()).unwrap();
+    let mut user_events = users.subscribe(Bytes::new()).unwrap();
+    let mut changes = users.changes(users.change_cursor().unwrap()).unwrap();
+
+    handle.set(key(0), value(0)).unwrap();
+    users.set(key(1), value(1)).unwrap();
+    users.del(key(1)).unwrap();
+
+    assert_eq!(all.try_recv().unwrap().unwrap().key(), &key(0));
+    assert!(all.try_recv().unwrap().is_none());
+    assert_eq!(
+        kind(user_events.try_recv().unwrap().unwrap()),
+        ("put", key(1))
+    );
+    assert_eq!(
+        kind(user_events.try_recv().unwrap().unwrap()),
+        ("del", key(1))
+    );
+    assert_eq!(
+        kind(changes.try_next().unwrap().unwrap().0),
+        ("put", key(1))
+    );
+    assert_eq!(
+        kind(changes.try_next().unwrap().unwrap().0),
+        ("del", key(1))
+    );
+    assert!(changes.try_next().unwrap().is_none());
+}
+
+#[test]
+fn read_only_handles_follow_keyspaces() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(tmpdir.path());
+    let users = bitcask.keyspace("users").unwrap();
+    users.set(key(0), value(0)).unwrap();
+    users.sync().unwrap();
+
+    let options = Options::default().read_only(true);
+    let follower = Bitcask::open_with(tmpdir.path(), options).unwrap();
+    assert!(matches!(follower.keyspace("items"), Err(Error::ReadOnly)));
+    let followed = follower.keyspace("users").unwrap();
+    assert_eq!(followed.get(key(0)).unwrap(), Some(value(0)));
+
+    bitcask.drop_keyspace("users").unwrap();
+    followed.refresh().unwrap();
+    assert_eq!(followed.get(key(0)).unwrap(), None);
+    assert!(follower.keyspaces().is_empty());
+}
+
+#[test]
+fn checkpoints_carry_keyspaces() {
+    let tmpdir = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let dest = dest.path().join("checkpoint");
+    let bitcask = open(tmpdir.path());
+    bitcask
+        .keyspace("users")
+        .unwrap()
+        .set(key(0), value(0))
+        .unwrap();
+    bitcask
+        .keyspace("items")
+        .unwrap()
+        .set(key(0), value(1))
+        .unwrap();
+    bitcask.drop_keyspace("items").unwrap();
+    bitcask.get_handle().checkpoint(&dest).unwrap();
+
+    let copy = open(&dest);
+    assert_eq!(copy.keyspaces(), vec!["users"]);
+    assert_eq!(
+        copy.keyspace("users").unwrap().get(key(0)).unwrap(),
+        Some(value(0))
+    );
+    assert_eq!(copy.keyspace("items").unwrap().get(key(0)).unwrap(), None);
+}
@@ -0,0 +1,25 @@
+use std::process;
+
+use bitcask::{Bitcask, Error, KeyValueStorage};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, open, value};
+
+#[test]
+fn second_writer_is_locked_out_until_the_first_is_dropped() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir);
+    bitcask.get_handle().set(key(0), value(0)).unwrap();
+
+    match Bitcask::open_with(tmpdir.path(), common::options()) {
+        Err(Error::Locked { pid }) => assert_eq!(pid, Some(process::id())),
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("opened a locked store"),
+    }
+
+    drop(bitcask);
+    let bitcask = open(&tmpdir);
+    assert_eq!(bitcask.get_handle().get(key(0)).unwrap(), Some(value(0)));
+}
@@ -0,0 +1,81 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+use bitcask::{Bitcask, KeyValueStorage};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, value};
+
+#[test]
+fn writes_and_reads_keep_working_while_merging() {
+    const KEYS: usize = 4;
+    const ROUNDS: usize = 100;
+
+    let tmpdir = TempDir::new().unwrap();
+    // Readers map only one file at a time, so that most reads open a file
+    // that a merge may have removed since the key was looked up.
+    let options = common::options()
+        .max_file_size(64)
+        .readers(4)
+        .reader_cache_size(1);
+    let bitcask = Bitcask::open_with(tmpdir.path(), options.clone()).unwrap();
+    let handle = bitcask.get_handle();
+    for i in 0..KEYS {
+        handle.set(key(i), value(i)).unwrap();
+    }
+
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        let merger = s.spawn(|| {
+            let mut merges = 0;
+            while !done.load(Ordering::Relaxed) {
+                handle.merge().unwrap();
+                merges += 1;
+            }
+            merges
+        });
+        // The odd keys never change and are only ever read from files that
+        // merges keep rewriting.
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                s.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        for i in (1..KEYS).step_by(2) {
+                            assert_eq!(handle.get(key(i)).unwrap(), Some(value(i)));
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for round in 1..ROUNDS {
+            for i in (0..KEYS).step_by(2) {
+                handle.set(key(i), value(round * KEYS + i)).unwrap();
+            }
+            for i in (0..KEYS).step_by(2) {
+                assert_eq!(handle.get(key(i)).unwrap(), Some(value(round * KEYS + i)));
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert!(merger.join().unwrap() > 0);
+    });
+
+    drop((handle, bitcask));
+    let bitcask = Bitcask::open_with(tmpdir.path(), options).unwrap();
+    let handle = bitcask.get_handle();
+    for i in 0..KEYS {
+        let expected = if i % 2 == 0 {
+            (ROUNDS - 1) * KEYS + i
+        } else {
+            i
+        };
+        assert_eq!(handle.get(key(i)).unwrap(), Some(value(expected)));
+    }
+}
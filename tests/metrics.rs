@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use bitcask::{Bitcask, CompactionOptions, KeyValueStorage, Options, WriteBatch};
+use bytes::Bytes;
+use tempfile::TempDir;
+
+mod common;
+
+use common::sample;
+
+#[test]
+fn metrics_count_operations_in_prometheus_format() {
+    let tmpdir = TempDir::new().unwrap();
+    let options = Options::default()
+        .compaction(CompactionOptions::default().enabled(false))
+        .max_file_size(64);
+    let bitcask = Bitcask::open_with(tmpdir.path(), options).unwrap();
+    let handle = bitcask.get_handle();
+    for i in 0..10 {
+        handle
+            .set(Bytes::from(format!("key{i}")), Bytes::from("value"))
+            .unwrap();
+    }
+    for i in 0..10 {
+        handle
+            .set(Bytes::from(format!("key{i}")), Bytes::from("other"))
+            .unwrap();
+    }
+    assert!(handle.get(Bytes::from("key0")).unwrap().is_some());
+    assert!(handle.get(Bytes::from("missing")).unwrap().is_none());
+    assert!(handle.del(Bytes::from("key1")).unwrap());
+    handle.sync().unwrap();
+    handle.merge().unwrap();
+
+    let metrics = handle.metrics().render();
+    assert_eq!(
+        sample(&metrics, "bitcask_operations_total{op=\"put\"}"),
+        20.0
+    );
+    assert_eq!(
+        sample(&metrics, "bitcask_operations_total{op=\"get\"}"),
+        2.0
+    );
+    assert_eq!(
+        sample(&metrics, "bitcask_operations_total{op=\"del\"}"),
+        1.0
+    );
+    assert_eq!(
+        sample(&metrics, "bitcask_operations_total{op=\"sync\"}"),
+        1.0
+    );
+    assert_eq!(
+        sample(&metrics, "bitcask_operation_errors_total{op=\"get\"}"),
+        0.0
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            "bitcask_operation_duration_seconds_bucket{op=\"put\",le=\"+Inf\"}"
+        ),
+        20.0
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            "bitcask_operation_duration_seconds_count{op=\"put\"}"
+        ),
+        20.0
+    );
+    assert_eq!(sample(&metrics, "bitcask_reader_wait_seconds_count"), 2.0);
+    assert_eq!(sample(&metrics, "bitcask_writer_wait_seconds_count"), 22.0);
+    assert!(sample(&metrics, "bitcask_rotations_total") > 1.0);
+    assert!(sample(&metrics, "bitcask_appended_bytes_total") > 0.0);
+    assert_eq!(sample(&metrics, "bitcask_merges_total"), 1.0);
+    assert!(sample(&metrics, "bitcask_merge_reclaimed_bytes_total") > 0.0);
+    assert!(metrics.contains("# TYPE bitcask_operation_duration_seconds histogram"));
+}
+
+#[test]
+fn failed_operations_are_counted() {
+    let tmpdir = TempDir::new().unwrap();
+    drop(Bitcask::open(tmpdir.path()).unwrap());
+    let bitcask = Bitcask::open_with(tmpdir.path(), Options::default().read_only(true)).unwrap();
+    let handle = bitcask.get_handle();
+    assert!(handle
+        .set(Bytes::from("key"), Bytes::from("value"))
+        .is_err());
+
+    let metrics = handle.metrics().render();
+    assert_eq!(
+        sample(&metrics, "bitcask_operations_total{op=\"put\"}"),
+        1.0
+    );
+    assert_eq!(
+        sample(&metrics, "bitcask_operation_errors_total{op=\"put\"}"),
+        1.0
+    );
+}
+
+#[test]
+fn every_kind_of_write_is_counted() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = common::open(tmpdir.path());
+    let handle = bitcask.get_handle();
+    handle
+        .set_with_ttl(Bytes::from("a"), Bytes::from("1"), Duration::from_secs(60))
+        .unwrap();
+    assert!(handle
+        .put_if_absent(Bytes::from("b"), Bytes::from("2"))
+        .unwrap());
+    assert!(!handle
+        .put_if_absent(Bytes::from("b"), Bytes::from("3"))
+        .unwrap());
+    assert!(handle
+        .delete_if_equals(Bytes::from("b"), Bytes::from("2"))
+        .unwrap());
+    let mut batch = WriteBatch::new();
+    batch.put(Bytes::from("c"), Bytes::from("3"));
+    batch.delete(Bytes::from("a"));
+    handle.write_batch(batch).unwrap();
+
+    let metrics = handle.metrics().render();
+    let ops = |op: &str| {
+        sample(
+            &metrics,
+            &format!("bitcask_operations_total{{op=\"{op}\"}}"),
+        )
+    };
+    assert_eq!(ops("put"), 1.0);
+    assert_eq!(ops("cas"), 3.0);
+    assert_eq!(ops("batch"), 1.0);
+    assert_eq!(
+        sample(
+            &metrics,
+            "bitcask_operation_duration_seconds_count{op=\"cas\"}"
+        ),
+        3.0
+    );
+}
@@ -0,0 +1,58 @@
+use std::io;
+
+use bitcask::{Bitcask, Error, KeyValueStorage};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, open, value};
+
+#[test]
+fn create_if_missing_false_needs_an_existing_directory() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("store");
+    let options = common::options().create_if_missing(false);
+    match Bitcask::open_with(&path, options.clone()) {
+        Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("opened a missing store"),
+    }
+    assert!(!path.exists());
+
+    drop(open(&path));
+    Bitcask::open_with(&path, options).unwrap();
+}
+
+#[test]
+fn error_if_exists_rejects_a_store_with_data() {
+    let tmpdir = TempDir::new().unwrap();
+    let options = common::options().error_if_exists(true);
+    // An empty directory holds no store yet.
+    let bitcask = Bitcask::open_with(tmpdir.path(), options.clone()).unwrap();
+    bitcask.get_handle().set(key(0), value(0)).unwrap();
+    drop(bitcask);
+
+    assert!(matches!(
+        Bitcask::open_with(tmpdir.path(), options),
+        Err(Error::AlreadyExists)
+    ));
+}
+
+#[test]
+fn zero_readers_and_cache_size_are_clamped_to_one() {
+    let tmpdir = TempDir::new().unwrap();
+    let options = common::options()
+        .max_file_size(64)
+        .readers(0)
+        .reader_cache_size(0);
+    let bitcask = Bitcask::open_with(tmpdir.path(), options).unwrap();
+    let handle = bitcask.get_handle();
+    // Spread the keys over several data files, more than one reader can keep
+    // mapped at a time.
+    for i in 0..10 {
+        handle.set(key(i), value(i)).unwrap();
+    }
+    for i in (0..10).rev() {
+        assert_eq!(handle.get(key(i)).unwrap(), Some(value(i)));
+    }
+}
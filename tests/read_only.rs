@@ -0,0 +1,163 @@
+use std::{
+    fs,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use bitcask::{Bitcask, Error, KeyValueStorage, WriteBatch};
+use bytes::Bytes;
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, value};
+
+fn open(tmpdir: &TempDir, read_only: bool) -> Bitcask {
+    let options = common::options().max_file_size(64).read_only(read_only);
+    Bitcask::open_with(tmpdir.path(), options).unwrap()
+}
+
+#[test]
+fn read_only_store_rejects_writes_and_leaves_files_alone() {
+    let tmpdir = TempDir::new().unwrap();
+    {
+        let bitcask = open(&tmpdir, false);
+        bitcask.get_handle().set(key(0), value(0)).unwrap();
+    }
+    let files = || {
+        let mut names: Vec<_> = fs::read_dir(tmpdir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        names.sort();
+        names
+    };
+    let before = files();
+
+    {
+        let bitcask = open(&tmpdir, true);
+        let handle = bitcask.get_handle();
+        assert_eq!(handle.get(key(0)).unwrap(), Some(value(0)));
+        assert!(matches!(handle.set(key(1), value(1)), Err(Error::ReadOnly)));
+        assert!(matches!(handle.del(key(0)), Err(Error::ReadOnly)));
+    }
+    assert_eq!(files(), before);
+}
+
+#[test]
+fn read_only_store_follows_live_writer() {
+    let tmpdir = TempDir::new().unwrap();
+    let writer = open(&tmpdir, false);
+    let writer = writer.get_handle();
+    writer.set(key(0), value(0)).unwrap();
+
+    let follower = open(&tmpdir, true);
+    let follower = follower.get_handle();
+    assert_eq!(follower.get(key(0)).unwrap(), Some(value(0)));
+
+    // Enough writes to rotate through several data files.
+    for i in 1..16 {
+        writer.set(key(i), value(i)).unwrap();
+    }
+    writer.del(key(0)).unwrap();
+    assert_eq!(follower.get(key(1)).unwrap(), None);
+
+    follower.refresh().unwrap();
+    assert_eq!(follower.get(key(0)).unwrap(), None);
+    for i in 1..16 {
+        assert_eq!(follower.get(key(i)).unwrap(), Some(value(i)));
+    }
+
+    writer.merge().unwrap();
+    writer.set(key(1), value(0)).unwrap();
+    follower.refresh().unwrap();
+    assert_eq!(follower.get(key(1)).unwrap(), Some(value(0)));
+    for i in 2..16 {
+        assert_eq!(follower.get(key(i)).unwrap(), Some(value(i)));
+    }
+}
+
+#[test]
+fn read_only_store_applies_replayed_batches_in_one_step() {
+    const KEYS: usize = 64;
+    let tmpdir = TempDir::new().unwrap();
+    let writer = Bitcask::open_with(tmpdir.path(), common::options()).unwrap();
+    let writer = writer.get_handle();
+    let follower = open(&tmpdir, true);
+    let follower = follower.get_handle();
+    let done = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                follower.refresh().unwrap();
+            }
+        });
+        s.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                // Batches write the keys in order, so the last one is never
+                // behind the first unless a batch is seen half applied.
+                let first = follower.get(key(0)).unwrap();
+                let last = follower.get(key(KEYS - 1)).unwrap();
+                assert!(last >= first, "{last:?} is behind {first:?}");
+            }
+        });
+        for round in 0..200u64 {
+            let mut batch = WriteBatch::new();
+            for i in 0..KEYS {
+                batch.put(key(i), Bytes::from(round.to_be_bytes().to_vec()));
+            }
+            writer.write_batch(batch).unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+    });
+}
+
+#[test]
+fn read_only_store_refreshes_with_a_zero_interval() {
+    let tmpdir = TempDir::new().unwrap();
+    let writer = open(&tmpdir, false);
+    let writer = writer.get_handle();
+    let options = common::options()
+        .read_only(true)
+        .refresh_interval(Duration::ZERO);
+    let follower = Bitcask::open_with(tmpdir.path(), options).unwrap();
+    let follower = follower.get_handle();
+
+    writer.set(key(0), value(0)).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while follower.get(key(0)).unwrap().is_none() {
+        assert!(Instant::now() < deadline, "the write was never picked up");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn read_only_store_reads_a_record_mapped_while_half_written() {
+    let (datafile, first_len) = {
+        let tmpdir = TempDir::new().unwrap();
+        let bitcask = Bitcask::open_with(tmpdir.path(), common::options()).unwrap();
+        let handle = bitcask.get_handle();
+        let path = tmpdir.path().join("0.bitcask.data");
+        handle.set(key(0), value(0)).unwrap();
+        let first_len = fs::metadata(&path).unwrap().len() as usize;
+        handle.set(key(1), value(1)).unwrap();
+        (fs::read(&path).unwrap(), first_len)
+    };
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("0.bitcask.data");
+    let half = (first_len + datafile.len()) / 2;
+    fs::write(&path, &datafile[..half]).unwrap();
+
+    // A single reader, which maps the data file with half of the second
+    // record in it.
+    let options = common::options().read_only(true).readers(1);
+    let follower = Bitcask::open_with(tmpdir.path(), options).unwrap();
+    let follower = follower.get_handle();
+    assert_eq!(follower.get(key(0)).unwrap(), Some(value(0)));
+
+    fs::write(&path, &datafile).unwrap();
+    follower.refresh().unwrap();
+    assert_eq!(follower.get(key(1)).unwrap(), Some(value(1)));
+}
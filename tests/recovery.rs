@@ -0,0 +1,125 @@
+use std::fs;
+
+use bitcask::{Bitcask, Error, KeyValueStorage};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, open, value};
+
+const RECORDS: usize = 16;
+
+/// Length of the header every file starts with.
+const FILE_HEADER_LEN: usize = 8;
+
+/// Returns the contents of a data file as it looked before the writer was
+/// dropped, i.e. without a hint file sealing it.
+fn unsealed_datafile() -> Vec<u8> {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir);
+    let handle = bitcask.get_handle();
+    for i in 0..RECORDS {
+        handle.set(key(i), value(i)).unwrap();
+    }
+    fs::read(tmpdir.path().join("0.bitcask.data")).unwrap()
+}
+
+#[test]
+fn open_truncates_torn_tail_at_every_offset() {
+    let datafile = unsealed_datafile();
+    // All records have the same size, so a prefix holds
+    // `(len - FILE_HEADER_LEN) / record_len` complete records.
+    let record_len = (datafile.len() - FILE_HEADER_LEN) / RECORDS;
+
+    for len in 0..=datafile.len() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("0.bitcask.data");
+        fs::write(&path, &datafile[..len]).unwrap();
+
+        let complete = len.saturating_sub(FILE_HEADER_LEN) / record_len;
+        // A file cut short within its header is emptied.
+        let valid_len = match len {
+            0..FILE_HEADER_LEN => 0,
+            _ => FILE_HEADER_LEN + complete * record_len,
+        };
+        {
+            let bitcask = open(&tmpdir);
+            let handle = bitcask.get_handle();
+            for i in 0..RECORDS {
+                let expected = (i < complete).then(|| value(i));
+                assert_eq!(
+                    handle.get(key(i)).unwrap(),
+                    expected,
+                    "prefix of {len} bytes"
+                );
+            }
+            handle.set(key(RECORDS), value(RECORDS)).unwrap();
+        }
+        assert_eq!(fs::metadata(&path).unwrap().len(), valid_len as u64);
+
+        let bitcask = open(&tmpdir);
+        let handle = bitcask.get_handle();
+        assert_eq!(handle.get(key(RECORDS)).unwrap(), Some(value(RECORDS)));
+    }
+}
+
+#[test]
+fn open_truncates_corrupt_last_record() {
+    let mut datafile = unsealed_datafile();
+    let last = datafile.len() - 1;
+    datafile[last] ^= 0xff;
+
+    let tmpdir = TempDir::new().unwrap();
+    fs::write(tmpdir.path().join("0.bitcask.data"), &datafile).unwrap();
+
+    let bitcask = open(&tmpdir);
+    let handle = bitcask.get_handle();
+    assert_eq!(
+        handle.get(key(RECORDS - 2)).unwrap(),
+        Some(value(RECORDS - 2))
+    );
+    assert_eq!(handle.get(key(RECORDS - 1)).unwrap(), None);
+}
+
+/// Flips a bit in the payload of record `i` of `datafile`.
+fn corrupt_record(datafile: &mut [u8], i: usize) {
+    let record_len = (datafile.len() - FILE_HEADER_LEN) / RECORDS;
+    datafile[FILE_HEADER_LEN + i * record_len + 14] ^= 0x01;
+}
+
+#[test]
+fn open_refuses_to_truncate_from_a_corrupt_first_record() {
+    let mut datafile = unsealed_datafile();
+    corrupt_record(&mut datafile, 0);
+
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("0.bitcask.data");
+    fs::write(&path, &datafile).unwrap();
+
+    match Bitcask::open_with(tmpdir.path(), common::options()) {
+        Err(Error::Corruption { fileid: 0, pos }) => assert_eq!(pos, FILE_HEADER_LEN as u64),
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("opened a store with a corrupt first record"),
+    }
+    assert_eq!(fs::read(&path).unwrap(), datafile);
+}
+
+#[test]
+fn open_refuses_to_truncate_from_a_corrupt_record_after_valid_ones() {
+    let mut datafile = unsealed_datafile();
+    corrupt_record(&mut datafile, RECORDS / 2);
+
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("0.bitcask.data");
+    fs::write(&path, &datafile).unwrap();
+
+    let record_len = (datafile.len() - FILE_HEADER_LEN) / RECORDS;
+    match Bitcask::open_with(tmpdir.path(), common::options()) {
+        Err(Error::Corruption { fileid: 0, pos }) => {
+            assert_eq!(pos, (FILE_HEADER_LEN + RECORDS / 2 * record_len) as u64)
+        }
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("opened a store with a corrupt record mid-file"),
+    }
+    assert_eq!(fs::read(&path).unwrap(), datafile);
+}
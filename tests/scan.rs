@@ -0,0 +1,83 @@
+use bitcask::{Handle, KeyValueStorage};
+use bytes::Bytes;
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, open_small, value};
+
+fn collect(iter: impl Iterator<Item = Result<(Bytes, Bytes), bitcask::Error>>) -> Vec<Bytes> {
+    iter.map(|entry| {
+        let (key, value) = entry.unwrap();
+        assert_eq!(value.slice(5..), key.slice(3..));
+        key
+    })
+    .collect()
+}
+
+fn populate(handle: &Handle) {
+    for i in (0..20).rev() {
+        handle.set(key(i), value(i)).unwrap();
+    }
+    handle.del(key(5)).unwrap();
+}
+
+#[test]
+fn range_yields_live_entries_in_key_order() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open_small(&tmpdir);
+    let handle = bitcask.get_handle();
+    populate(&handle);
+
+    let expected: Vec<_> = (3..8).filter(|&i| i != 5).map(key).collect();
+    assert_eq!(collect(handle.range(key(3)..key(8))), expected);
+
+    let expected: Vec<_> = (3..=8).rev().filter(|&i| i != 5).map(key).collect();
+    assert_eq!(collect(handle.range_rev(key(3)..=key(8))), expected);
+
+    assert_eq!(collect(handle.range(..)).len(), 19);
+    assert_eq!(collect(handle.range(key(18)..)), vec![key(18), key(19)]);
+    assert_eq!(collect(handle.range_rev(..key(2))), vec![key(1), key(0)]);
+}
+
+#[test]
+fn range_sees_writes_ahead_of_the_cursor() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open_small(&tmpdir);
+    let handle = bitcask.get_handle();
+    populate(&handle);
+
+    let mut range = handle.range(key(0)..key(10));
+    assert_eq!(range.next().unwrap().unwrap().0, key(0));
+    handle.del(key(1)).unwrap();
+    handle.set(key(5), value(5)).unwrap();
+    let rest = collect(range);
+    assert_eq!(rest, (2..10).map(key).collect::<Vec<_>>());
+}
+
+#[test]
+fn prefix_scans_stop_at_the_end_of_the_prefix() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open_small(&tmpdir);
+    let handle = bitcask.get_handle();
+    populate(&handle);
+    for k in ["kex", "kez", "ke\u{7f}"] {
+        handle.set(Bytes::from(k), Bytes::from("other")).unwrap();
+    }
+    handle
+        .set(Bytes::from(&b"\xff\xff"[..]), Bytes::from("max"))
+        .unwrap();
+
+    let expected: Vec<_> = (10..20).map(key).collect();
+    assert_eq!(collect(handle.scan_prefix(Bytes::from("key1"))), expected);
+    assert_eq!(
+        handle.keys(Bytes::from("key0")).collect::<Vec<_>>(),
+        (0..10).filter(|&i| i != 5).map(key).collect::<Vec<_>>()
+    );
+    assert_eq!(handle.keys(Bytes::from("key")).count(), 19);
+    assert_eq!(
+        handle.keys(Bytes::from(&b"\xff"[..])).collect::<Vec<_>>(),
+        vec![Bytes::from(&b"\xff\xff"[..])]
+    );
+    assert_eq!(handle.keys(Bytes::new()).count(), 23);
+}
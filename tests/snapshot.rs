@@ -0,0 +1,58 @@
+use std::fs;
+
+use bitcask::KeyValueStorage;
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, open_small, value};
+
+fn tmpfiles(tmpdir: &TempDir) -> usize {
+    fs::read_dir(tmpdir.path())
+        .unwrap()
+        .filter(|e| {
+            e.as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "tmp")
+        })
+        .count()
+}
+
+#[test]
+fn snapshot_ignores_later_writes_and_outlives_merge() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open_small(&tmpdir);
+    let handle = bitcask.get_handle();
+    for i in 0..10 {
+        handle.set(key(i), value(i)).unwrap();
+    }
+
+    let snapshot = handle.snapshot().unwrap();
+    for i in 0..5 {
+        handle.set(key(i), value(i + 10)).unwrap();
+    }
+    handle.del(key(9)).unwrap();
+    handle.set(key(10), value(10)).unwrap();
+    handle.merge().unwrap();
+    // The files merged away are kept aside while the snapshot reads them.
+    assert!(tmpfiles(&tmpdir) > 0);
+
+    for i in 0..10 {
+        assert_eq!(snapshot.get(key(i)).unwrap(), Some(value(i)));
+    }
+    assert_eq!(snapshot.get(key(10)).unwrap(), None);
+    let keys: Vec<_> = snapshot
+        .range_rev(key(7)..)
+        .map(|entry| entry.unwrap().0)
+        .collect();
+    assert_eq!(keys, vec![key(9), key(8), key(7)]);
+    assert_eq!(snapshot.range(..).count(), 10);
+
+    assert_eq!(handle.get(key(0)).unwrap(), Some(value(10)));
+    assert_eq!(handle.get(key(9)).unwrap(), None);
+
+    drop(snapshot);
+    assert_eq!(tmpfiles(&tmpdir), 0);
+}
@@ -0,0 +1,44 @@
+use bitcask::KeyValueStorage;
+use tempfile::TempDir;
+
+mod common;
+
+use common::{key, open_small, value};
+
+#[test]
+fn stats_track_live_and_dead_entries_per_file() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open_small(tmpdir.path());
+    let handle = bitcask.get_handle();
+    for i in 0..10 {
+        handle.set(key(i), value(i)).unwrap();
+    }
+    handle.set(key(0), value(10)).unwrap();
+    handle.del(key(1)).unwrap();
+
+    let stats = handle.stats().unwrap();
+    assert_eq!(stats.keys, 9);
+    assert_eq!(stats.live_keys, 9);
+    // The overwritten and the deleted value, and the tombstone.
+    assert_eq!(stats.dead_keys, 3);
+    assert!(stats.files.len() > 1);
+    let active = stats.files.last().unwrap();
+    assert_eq!(stats.active_fileid, Some(active.fileid));
+    assert_eq!(stats.active_bytes, active.size);
+    assert_eq!(active.hint_size, 0);
+    assert!(stats.files[0].hint_size > 0);
+    assert_eq!(
+        stats.disk_size,
+        stats
+            .files
+            .iter()
+            .map(|f| f.size + f.hint_size)
+            .sum::<u64>()
+    );
+    assert!(stats.files[0].fragmentation > 0.0);
+
+    handle.merge().unwrap();
+    let stats = handle.stats().unwrap();
+    assert_eq!(stats.live_keys, 9);
+    assert_eq!(stats.dead_bytes, 0);
+}
@@ -0,0 +1,113 @@
+use bitcask::{AsyncKeyValueStorage, Bitcask, Error, Event, KeyValueStorage, WriteBatch};
+use bytes::Bytes;
+use tempfile::TempDir;
+
+mod common;
+
+fn open(tmpdir: &TempDir, capacity: usize) -> Bitcask {
+    let options = common::options().subscription_capacity(capacity);
+    Bitcask::open_with(tmpdir.path(), options).unwrap()
+}
+
+fn put(event: Event) -> (Bytes, Bytes) {
+    match event {
+        Event::Put { key, value, .. } => (key, value),
+        event => panic!("expected a put, got {event:?}"),
+    }
+}
+
+#[test]
+fn subscriptions_see_writes_to_their_prefix_in_order() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir, 16);
+    let handle = bitcask.get_handle();
+    handle.set(Bytes::from("user/0"), Bytes::from("a")).unwrap();
+    let mut users = handle.subscribe(Bytes::from("user/")).unwrap();
+    let mut all = handle.subscribe(Bytes::new()).unwrap();
+
+    handle.set(Bytes::from("user/1"), Bytes::from("b")).unwrap();
+    handle.set(Bytes::from("item/1"), Bytes::from("c")).unwrap();
+    handle.del(Bytes::from("user/0")).unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put(Bytes::from("user/2"), Bytes::from("d"));
+    batch.delete(Bytes::from("user/1"));
+    handle.write_batch(batch).unwrap();
+    assert!(!handle
+        .put_if_absent(Bytes::from("user/2"), Bytes::from("e"))
+        .unwrap());
+
+    let events: Vec<_> = std::iter::from_fn(|| users.try_recv().unwrap()).collect();
+    let keys: Vec<_> = events.iter().map(Event::key).cloned().collect();
+    assert_eq!(keys, ["user/1", "user/0", "user/2", "user/1"]);
+    assert_eq!(
+        put(events[0].clone()),
+        (Bytes::from("user/1"), Bytes::from("b"))
+    );
+    assert!(matches!(events[1], Event::Delete { .. }));
+    assert!(matches!(events[3], Event::Delete { .. }));
+    let tstamps: Vec<_> = events
+        .iter()
+        .map(|event| match event {
+            Event::Put { tstamp, .. } | Event::Delete { tstamp, .. } => *tstamp,
+        })
+        .collect();
+    assert!(tstamps.is_sorted());
+
+    assert_eq!(std::iter::from_fn(|| all.try_recv().unwrap()).count(), 5);
+}
+
+#[test]
+fn slow_subscribers_are_told_how_much_they_missed() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir, 2);
+    let handle = bitcask.get_handle();
+    let mut subscription = handle.subscribe(Bytes::new()).unwrap();
+    for i in 0..5 {
+        handle
+            .set(Bytes::from(format!("key{i}")), Bytes::from("value"))
+            .unwrap();
+    }
+
+    assert!(matches!(subscription.try_recv(), Err(Error::Lagged(3))));
+    assert_eq!(subscription.try_recv().unwrap().unwrap().key(), "key3");
+    assert_eq!(subscription.try_recv().unwrap().unwrap().key(), "key4");
+    assert!(subscription.try_recv().unwrap().is_none());
+}
+
+#[test]
+fn subscriptions_end_when_the_store_closes() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir, 16);
+    let handle = bitcask.get_handle();
+    let mut subscription = handle.subscribe(Bytes::new()).unwrap();
+    handle
+        .set(Bytes::from("key"), Bytes::from("value"))
+        .unwrap();
+    drop(bitcask);
+
+    assert_eq!(subscription.blocking_recv().unwrap().key(), "key");
+    assert!(matches!(subscription.blocking_recv(), Err(Error::Closed)));
+    assert!(matches!(handle.subscribe(Bytes::new()), Err(Error::Closed)));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn subscriptions_wake_up_async_tasks() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir, 16);
+    let handle = bitcask.get_async_handle();
+    let mut subscription = handle.subscribe(Bytes::from("key")).unwrap();
+    let listener = tokio::spawn(async move { subscription.recv().await.unwrap() });
+
+    handle
+        .set(Bytes::from("other"), Bytes::from("value"))
+        .await
+        .unwrap();
+    handle
+        .set(Bytes::from("key"), Bytes::from("value"))
+        .await
+        .unwrap();
+    assert_eq!(
+        put(listener.await.unwrap()),
+        (Bytes::from("key"), Bytes::from("value"))
+    );
+}
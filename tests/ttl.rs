@@ -0,0 +1,182 @@
+use std::{
+    fs, thread,
+    time::{Duration, Instant},
+};
+
+use bitcask::{Bitcask, CompactionOptions, KeyValueStorage};
+use bytes::Bytes;
+use tempfile::TempDir;
+
+mod common;
+
+use common::{open, sample};
+
+const TTL: Duration = Duration::from_millis(100);
+
+fn datafiles_len(tmpdir: &TempDir) -> u64 {
+    fs::read_dir(tmpdir.path())
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "data"))
+        .map(|p| fs::metadata(p).unwrap().len())
+        .sum()
+}
+
+#[test]
+fn expired_key_reads_as_deleted() {
+    let tmpdir = TempDir::new().unwrap();
+    {
+        let bitcask = open(&tmpdir);
+        let handle = bitcask.get_handle();
+        handle
+            .set_with_ttl(Bytes::from("session"), Bytes::from("token"), TTL)
+            .unwrap();
+        handle
+            .set(Bytes::from("user"), Bytes::from("name"))
+            .unwrap();
+        assert_eq!(
+            handle.get(Bytes::from("session")).unwrap(),
+            Some(Bytes::from("token"))
+        );
+
+        thread::sleep(TTL);
+        assert_eq!(handle.get(Bytes::from("session")).unwrap(), None);
+        assert_eq!(
+            handle.keys(Bytes::new()).collect::<Vec<_>>(),
+            vec![Bytes::from("user")]
+        );
+    }
+
+    let bitcask = open(&tmpdir);
+    let handle = bitcask.get_handle();
+    assert_eq!(handle.get(Bytes::from("session")).unwrap(), None);
+    assert_eq!(
+        handle.get(Bytes::from("user")).unwrap(),
+        Some(Bytes::from("name"))
+    );
+}
+
+#[test]
+fn merge_drops_expired_values() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir);
+    let handle = bitcask.get_handle();
+    let value = Bytes::from(vec![0; 4096]);
+    handle
+        .set_with_ttl(Bytes::from("session"), value.clone(), TTL)
+        .unwrap();
+    handle
+        .set_with_ttl(
+            Bytes::from("other"),
+            value.clone(),
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+
+    thread::sleep(TTL);
+    handle.merge().unwrap();
+    assert!(datafiles_len(&tmpdir) < 2 * 4096);
+    assert_eq!(handle.get(Bytes::from("session")).unwrap(), None);
+    assert_eq!(handle.get(Bytes::from("other")).unwrap(), Some(value));
+}
+
+#[test]
+fn stats_count_expired_values_as_dead_without_compaction() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir);
+    let handle = bitcask.get_handle();
+    handle
+        .set_with_ttl(Bytes::from("session"), Bytes::from("token"), TTL)
+        .unwrap();
+    handle
+        .set(Bytes::from("user"), Bytes::from("name"))
+        .unwrap();
+    let stats = handle.stats().unwrap();
+    assert_eq!((stats.keys, stats.live_keys, stats.dead_keys), (2, 2, 0));
+
+    let follower = Bitcask::open_with(&tmpdir, common::options().read_only(true)).unwrap();
+    let follower = follower.get_handle();
+
+    thread::sleep(TTL);
+    for handle in [handle, follower] {
+        let stats = handle.stats().unwrap();
+        assert_eq!((stats.keys, stats.live_keys, stats.dead_keys), (1, 1, 1));
+        assert!(stats.dead_bytes > 0);
+    }
+}
+
+#[test]
+fn values_written_over_a_ttl_do_not_expire() {
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = open(&tmpdir);
+    let handle = bitcask.get_handle();
+    for key in ["kept", "deleted", "extended"] {
+        handle
+            .set_with_ttl(Bytes::from(key), Bytes::from("old"), TTL)
+            .unwrap();
+    }
+    handle.set(Bytes::from("kept"), Bytes::from("new")).unwrap();
+    handle.del(Bytes::from("deleted")).unwrap();
+    handle
+        .set_with_ttl(Bytes::from("extended"), Bytes::from("new"), TTL * 100)
+        .unwrap();
+
+    thread::sleep(TTL);
+    let stats = handle.stats().unwrap();
+    assert_eq!((stats.keys, stats.live_keys), (2, 2));
+    assert_eq!(
+        handle.get(Bytes::from("kept")).unwrap(),
+        Some(Bytes::from("new"))
+    );
+    assert_eq!(
+        handle.get(Bytes::from("extended")).unwrap(),
+        Some(Bytes::from("new"))
+    );
+}
+
+#[test]
+fn merge_keeps_a_tombstone_for_an_expired_value_that_shadows_an_older_one() {
+    let tmpdir = TempDir::new().unwrap();
+    let compaction = CompactionOptions::default()
+        .interval(Duration::from_millis(10))
+        .min_fragmentation(f64::INFINITY)
+        .min_dead_bytes(1024);
+    let options = common::options().max_file_size(64).compaction(compaction);
+    {
+        let bitcask = Bitcask::open_with(tmpdir.path(), options.clone()).unwrap();
+        let handle = bitcask.get_handle();
+        handle
+            .set(Bytes::from("session"), Bytes::from("old"))
+            .unwrap();
+        handle
+            .set(Bytes::from("padding"), Bytes::from(vec![0; 64]))
+            .unwrap();
+        // Only the file holding the expired value has enough dead bytes to
+        // be merged, so the older value stays behind.
+        handle
+            .set_with_ttl(Bytes::from("session"), Bytes::from(vec![0; 4096]), TTL)
+            .unwrap();
+        handle
+            .set(Bytes::from("user"), Bytes::from("name"))
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while sample(&handle.metrics().render(), "bitcask_merges_total") == 0.0 {
+            assert!(Instant::now() < deadline, "nothing was merged");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(datafiles_len(&tmpdir) < 4096);
+    }
+
+    let bitcask = Bitcask::open_with(tmpdir.path(), options).unwrap();
+    let handle = bitcask.get_handle();
+    assert_eq!(handle.get(Bytes::from("session")).unwrap(), None);
+    assert_eq!(
+        handle.get(Bytes::from("user")).unwrap(),
+        Some(Bytes::from("name"))
+    );
+    assert_eq!(
+        handle.get(Bytes::from("padding")).unwrap().unwrap().len(),
+        64
+    );
+}